@@ -0,0 +1,769 @@
+use crate::error::RegexError;
+use crate::nfa::{parse_pattern, CompileConfig, LineTerminator, NFAGraph};
+
+pub const DEFAULT_SIZE_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    pattern: String,
+    config: CompileConfig,
+    active_state_limit: usize,
+    dot_matches_newline: bool,
+    allow_empty: bool,
+    anchored: bool,
+    whole_word: bool,
+    ascii: bool,
+    #[cfg(feature = "unicode-normalization")]
+    normalize: bool,
+}
+
+impl RegexBuilder {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            config: CompileConfig::default(),
+            active_state_limit: usize::MAX,
+            dot_matches_newline: false,
+            allow_empty: true,
+            anchored: false,
+            whole_word: false,
+            ascii: true,
+            #[cfg(feature = "unicode-normalization")]
+            normalize: false,
+        }
+    }
+
+    // Maximum number of states the compiled graph may allocate.
+    pub fn size_limit(&mut self, limit: usize) -> &mut Self {
+        self.config.size_limit = limit;
+        self
+    }
+
+    // Compile for `NFAGraph::is_match_bytes`, matching the UTF-8 encoding of
+    // the pattern byte by byte.
+    pub fn bytes(&mut self, yes: bool) -> &mut Self {
+        self.config.bytes = yes;
+        self
+    }
+
+    // Compile alternations of plain literals, like a long list of words,
+    // into a trie whose branches share their common prefixes. This saves
+    // many states, but `find_tagged` can no longer tell the words apart.
+    pub fn trie(&mut self, yes: bool) -> &mut Self {
+        self.config.trie = yes;
+        self
+    }
+
+    // Compile `{min,max}` to a single loop with a counter instead of `max`
+    // copies of what it repeats, so `a{1000}` stays a handful of states.
+    // Matching keeps a counter per loop in every thread, so it can take
+    // longer on ambiguous patterns, and `to_dfa` rejects the graph.
+    pub fn counted_loops(&mut self, yes: bool) -> &mut Self {
+        self.config.counted_loops = yes;
+        self
+    }
+
+    // Let `^` and `$` also match right after and right before a `\n`.
+    pub fn multiline(&mut self, yes: bool) -> &mut Self {
+        self.config.multiline = yes;
+        self
+    }
+
+    // What ends a line for the multiline `^` and `$` and for the `.`
+    // wildcard, `\n` by default. With `Crlf`, `$` matches before `\r\n` but
+    // not between the two, and `.` skips both chars.
+    pub fn line_terminator(&mut self, terminator: LineTerminator) -> &mut Self {
+        self.config.line_terminator = terminator;
+        self
+    }
+
+    // Let the `.` wildcard match the line terminator, which it skips by
+    // default.
+    pub fn dot_matches_newline(&mut self, yes: bool) -> &mut Self {
+        self.dot_matches_newline = yes;
+        self
+    }
+
+    // Whether `find_iter` and the APIs built on it report empty matches.
+    // They always advance past one by a char either way.
+    pub fn allow_empty(&mut self, yes: bool) -> &mut Self {
+        self.allow_empty = yes;
+        self
+    }
+
+    // Let `find` and the APIs built on it only report matches starting right
+    // where the search starts, as if the pattern began with `^` there.
+    pub fn anchored(&mut self, yes: bool) -> &mut Self {
+        self.anchored = yes;
+        self
+    }
+
+    // Only match where the match is a whole word, as if the pattern were
+    // surrounded by `\b`, so `cat` doesn't match inside "category".
+    pub fn whole_word(&mut self, yes: bool) -> &mut Self {
+        self.whole_word = yes;
+        self
+    }
+
+    // Whether `\d`, `\s`, `\w` and the POSIX classes only match ASCII, which
+    // is the default. Otherwise they use the Unicode classes, `\d` matching
+    // any numeric char.
+    pub fn ascii(&mut self, yes: bool) -> &mut Self {
+        self.ascii = yes;
+        self
+    }
+
+    // Bring the pattern and every input of `is_match`, `is_full_match`,
+    // `is_match_chars`, `try_is_match` and `is_match_within` into Unicode
+    // NFC, so a composed `é` and an `e` followed by a combining acute accent
+    // are the same. This costs a normalization pass over each input, which
+    // buffers the chars of each combining sequence. APIs that report offsets
+    // match the input as given, since their offsets have to point into it.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn unicode_normalize(&mut self, yes: bool) -> &mut Self {
+        self.normalize = yes;
+        self
+    }
+
+    // Maximum number of simultaneously active states while matching. Going
+    // over it makes the checked match APIs fail with `TooManyStates`.
+    pub fn active_state_limit(&mut self, limit: usize) -> &mut Self {
+        self.active_state_limit = limit;
+        self
+    }
+
+    pub fn build(&self) -> Result<NFAGraph, RegexError> {
+        #[cfg(feature = "unicode-normalization")]
+        if self.normalize {
+            use unicode_normalization::UnicodeNormalization;
+            let mut graph = Self {
+                pattern: self.pattern.nfc().collect(),
+                normalize: false,
+                ..self.clone()
+            }
+            .build()?;
+            graph.normalize = true;
+            // the literal fast path compares the input as given
+            graph.literal = false;
+            return Ok(graph);
+        }
+        let (mut post, groups) = parse_pattern(&self.pattern, true, self.ascii)?;
+        if self.whole_word {
+            // wrapped in postfix, so error offsets still point into the
+            // pattern as written
+            post = if post.is_empty() {
+                "\\b\\b.".to_string()
+            } else {
+                format!("\\b{}.\\b.", post)
+            };
+        }
+        let mut graph = NFAGraph::compile_with(&post, &self.config)?;
+        graph.group_names = groups;
+        graph.active_state_limit = self.active_state_limit;
+        graph.dot_matches_newline = self.dot_matches_newline;
+        graph.line_terminator = self.config.line_terminator;
+        graph.allow_empty = self.allow_empty;
+        graph.anchored = self.anchored;
+        // the reversed graph was built before the wildcard options were set
+        graph.index_reversed();
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RegexBuilder, DEFAULT_SIZE_LIMIT};
+    use crate::error::{MatchError, RegexError};
+    use crate::nfa::{LineTerminator, Match, NFAGraph};
+
+    #[test]
+    fn test_build() {
+        let graph = RegexBuilder::new("a+b+").build().unwrap();
+        assert!(graph.is_match("aabb"));
+        assert_eq!(
+            RegexBuilder::new("(ab").build().unwrap_err(),
+            RegexError::UnbalancedParen(0)
+        );
+        assert_eq!(
+            RegexBuilder::new("a(b(c)").build().unwrap_err(),
+            RegexError::UnbalancedParen(1)
+        );
+        assert_eq!(
+            RegexBuilder::new("é)").build().unwrap_err(),
+            RegexError::UnbalancedParen(2)
+        );
+    }
+
+    #[test]
+    fn test_dangling_quantifier() {
+        for (pattern, at) in [
+            ("*a", 0),
+            ("(*a)", 1),
+            ("a(*b)", 2),
+            ("(a(*b))", 3),
+            ("((?))", 2),
+            ("a|+b", 2),
+            ("(a|?)", 3),
+            ("é(+)", 3),
+        ] {
+            assert_eq!(
+                RegexBuilder::new(pattern).build().unwrap_err(),
+                RegexError::DanglingQuantifier(at),
+                "{pattern}"
+            );
+        }
+        // a quantifier right after a group or another quantifier has an atom
+        assert!(RegexBuilder::new("(a)*").build().is_ok());
+        assert!(RegexBuilder::new("a+?").build().is_ok());
+        assert_eq!(
+            RegexBuilder::new("(?Pa)").build().unwrap_err(),
+            RegexError::IllegalPattern
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive_group() {
+        let graph = RegexBuilder::new("a(?i:b)c").build().unwrap();
+        assert!(graph.is_match("aBc"));
+        assert!(graph.is_match("abc"));
+        assert!(!graph.is_match("Abc"));
+        assert!(!graph.is_match("abC"));
+        assert_eq!(graph.capture_count(), 0);
+
+        let graph = RegexBuilder::new("(?i:x[a-c]+-\\xe9)y")
+            .ascii(false)
+            .build()
+            .unwrap();
+        assert!(graph.is_match("XaBC-\u{c9}y"));
+        assert!(!graph.is_match("xabc-\u{e9}Y"));
+        // ASCII mode only folds ASCII letters
+        let graph = RegexBuilder::new("(?i:x[a-c]+-\\xe9)y").build().unwrap();
+        assert!(graph.is_match("XaBC-\u{e9}y"));
+        assert!(!graph.is_match("XaBC-\u{c9}y"));
+        let graph = RegexBuilder::new("(?i:[a-z]+)").build().unwrap();
+        assert!(!graph.is_match("\u{212a}"));
+        // the flag ends with its group, also inside nested groups
+        let graph = RegexBuilder::new("(?i:a(b))(c)").build().unwrap();
+        assert!(graph.is_match("ABc"));
+        assert!(!graph.is_match("ABC"));
+        let graph = RegexBuilder::new("(?i:[^a])").build().unwrap();
+        assert!(!graph.is_match("A"));
+        assert!(graph.is_match("b"));
+
+        assert_eq!(
+            RegexBuilder::new("(?x:a)").build().unwrap_err(),
+            RegexError::UnknownFlag('x')
+        );
+        assert_eq!(
+            RegexBuilder::new("(?i)a").build().unwrap_err(),
+            RegexError::IllegalPattern
+        );
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_case_folding() {
+        // capital, small and final sigma all fold to the same char
+        for pattern in ["(?i:[\u{3a3}])", "(?i:[\u{3c2}])", "(?i:\u{3c3})"] {
+            let graph = RegexBuilder::new(pattern).ascii(false).build().unwrap();
+            for text in ["\u{3a3}", "\u{3c3}", "\u{3c2}"] {
+                assert!(graph.is_full_match(text), "{pattern} {text}");
+            }
+        }
+        let graph = RegexBuilder::new("(?i:[a-z]+)")
+            .ascii(false)
+            .build()
+            .unwrap();
+        assert!(graph.is_full_match("\u{212a}s\u{17f}"));
+        // only simple folding: the dotless i and sharp s keep to themselves
+        let graph = RegexBuilder::new("(?i:\u{131}|\u{df})")
+            .ascii(false)
+            .build()
+            .unwrap();
+        assert!(graph.is_full_match("\u{131}"));
+        assert!(!graph.is_match("I"));
+        assert!(graph.is_full_match("\u{1e9e}"));
+        assert!(!graph.is_match("SS"));
+    }
+
+    #[test]
+    fn test_size_limit() {
+        assert!(RegexBuilder::new("a+b+").size_limit(8).build().is_ok());
+        assert_eq!(
+            RegexBuilder::new("a+b+").size_limit(4).build().unwrap_err(),
+            RegexError::SizeLimitExceeded(4)
+        );
+        let pattern = "a".repeat(10_000);
+        assert_eq!(
+            RegexBuilder::new(&pattern).build().unwrap_err(),
+            RegexError::SizeLimitExceeded(10_000)
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        let graph = RegexBuilder::new("a+b").bytes(true).build().unwrap();
+        assert!(graph.is_match_bytes(b"aab"));
+        assert!(!graph.is_match_bytes(b"ba"));
+        let input = b"aa\xffb";
+        assert!(!graph.is_match_bytes(input));
+        assert!(!graph.is_match_bytes(b"aab\xff"));
+
+        // non-ASCII literals match their UTF-8 encoding
+        let graph = RegexBuilder::new("xé+").bytes(true).build().unwrap();
+        assert!(graph.is_match_bytes("xéé".as_bytes()));
+        assert!(!graph.is_match_bytes(b"x\xc3"));
+        assert!(!graph.is_match_bytes(b"x\xc3\xa9\xc3"));
+
+        let graph = RegexBuilder::new("[ab]+").bytes(true).build().unwrap();
+        assert!(graph.is_match_bytes(b"abba"));
+        assert!(!graph.is_match_bytes(b""));
+
+        // empty input is matched like `is_match` does
+        let graph = RegexBuilder::new("a*").bytes(true).build().unwrap();
+        assert!(graph.is_match_bytes(b""));
+        assert_eq!(graph.is_match_bytes(b""), graph.is_match(""));
+        assert_eq!(
+            RegexBuilder::new("[^a]").bytes(true).build().unwrap_err(),
+            RegexError::Unsupported("non-ASCII classes in bytes mode")
+        );
+    }
+
+    #[test]
+    fn test_multiline() {
+        let graph = RegexBuilder::new("^b").build().unwrap();
+        assert_eq!(graph.find("a\nbc"), None);
+        let graph = RegexBuilder::new("^b").multiline(true).build().unwrap();
+        assert_eq!(graph.find("a\nbc"), Some(Match { start: 2, end: 3 }));
+
+        let graph = RegexBuilder::new("a$").multiline(true).build().unwrap();
+        assert_eq!(graph.find("ba\nc"), Some(Match { start: 1, end: 2 }));
+        assert_eq!(graph.find_iter("a\na").count(), 2);
+        let graph = RegexBuilder::new("a$").build().unwrap();
+        assert_eq!(
+            graph.find_iter("a\na").collect::<Vec<_>>(),
+            vec![Match { start: 2, end: 3 }]
+        );
+    }
+
+    #[test]
+    fn test_absolute_anchors() {
+        // `\A` and `\z` ignore line boundaries, `^` and `$` don't
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let graph = RegexBuilder::new(pattern).multiline(true).build().unwrap();
+            graph.find_iter(text).map(|m| (m.start, m.end)).collect()
+        };
+        assert_eq!(spans("\\Aa", "a\na"), vec![(0, 1)]);
+        assert_eq!(spans("^a", "a\na"), vec![(0, 1), (2, 3)]);
+        assert_eq!(spans("a\\z", "a\na"), vec![(2, 3)]);
+        assert_eq!(spans("a$", "a\na"), vec![(0, 1), (2, 3)]);
+        assert_eq!(spans("\\A\\z", ""), vec![(0, 0)]);
+        assert_eq!(spans("\\Ab", "ab"), vec![]);
+
+        let graph = RegexBuilder::new("\\Aa+\\z").build().unwrap();
+        assert!(graph.is_match("aa"));
+        assert_eq!(graph.find("aa\n"), None);
+        assert!(RegexBuilder::new("\\Z").build().is_err());
+    }
+
+    #[test]
+    fn test_line_terminator() {
+        let text = "ab\r\ncd\r\n";
+        let spans = |graph: &crate::nfa::NFAGraph| -> Vec<(usize, usize)> {
+            graph.find_iter(text).map(|m| (m.start, m.end)).collect()
+        };
+        let graph = RegexBuilder::new("^\\w+$")
+            .multiline(true)
+            .line_terminator(LineTerminator::Crlf)
+            .build()
+            .unwrap();
+        assert_eq!(spans(&graph), vec![(0, 2), (4, 6)]);
+        // with `\n` lines `$` only holds after the `\r`
+        let graph = RegexBuilder::new("^\\w+$").multiline(true).build().unwrap();
+        assert_eq!(spans(&graph), vec![]);
+        // no empty line between `\r` and `\n`
+        let graph = RegexBuilder::new("^$")
+            .multiline(true)
+            .line_terminator(LineTerminator::Crlf)
+            .build()
+            .unwrap();
+        assert_eq!(spans(&graph), vec![(8, 8)]);
+
+        let graph = RegexBuilder::new("a.b")
+            .line_terminator(LineTerminator::Crlf)
+            .build()
+            .unwrap();
+        assert!(!graph.is_match("a\rb"));
+        assert!(!graph.is_match("a\nb"));
+        let graph = RegexBuilder::new("a.b")
+            .line_terminator(LineTerminator::Cr)
+            .build()
+            .unwrap();
+        assert!(!graph.is_match("a\rb"));
+        assert!(graph.is_match("a\nb"));
+        assert!(graph.to_dfa().unwrap().is_match("a\nb"));
+        assert!(!graph.to_dfa().unwrap().is_match("a\rb"));
+    }
+
+    #[test]
+    fn test_dot_matches_newline() {
+        let graph = RegexBuilder::new("a.b").build().unwrap();
+        assert!(!graph.is_match("a\nb"));
+        assert!(graph.is_match("axb"));
+        let graph = RegexBuilder::new("a.b")
+            .dot_matches_newline(true)
+            .build()
+            .unwrap();
+        assert!(graph.is_match("a\nb"));
+    }
+
+    #[test]
+    fn test_allow_empty() {
+        let spans = |graph: &crate::nfa::NFAGraph| -> Vec<(usize, usize)> {
+            graph.find_iter("abc").map(|m| (m.start, m.end)).collect()
+        };
+        let graph = RegexBuilder::new("a*").build().unwrap();
+        assert_eq!(spans(&graph), vec![(0, 1), (1, 1), (2, 2), (3, 3)]);
+        let graph = RegexBuilder::new("a*").allow_empty(false).build().unwrap();
+        assert_eq!(spans(&graph), vec![(0, 1)]);
+        assert_eq!(graph.count_matches("abc"), 1);
+    }
+
+    #[test]
+    fn test_trie() {
+        let naive = RegexBuilder::new("foo|foobar|foobaz").build().unwrap();
+        let graph = RegexBuilder::new("foo|foobar|foobaz")
+            .trie(true)
+            .build()
+            .unwrap();
+        assert!(graph.states.len() < naive.states.len());
+        for text in ["foo", "foobar", "foobaz"] {
+            assert!(graph.is_full_match(text), "{text}");
+        }
+        assert!(!graph.is_match("fooba"));
+        assert_eq!(graph.find("xfoobaz"), Some(Match { start: 1, end: 7 }));
+
+        // word lists inside a larger pattern are factored too
+        let graph = RegexBuilder::new("x(ab|ac|b)+y|(ab|c)*")
+            .trie(true)
+            .build()
+            .unwrap();
+        for text in ["xacaby", "xby", "", "abcab"] {
+            assert!(graph.is_full_match(text), "{text}");
+        }
+        assert!(!graph.is_match("xy"));
+        assert!(!graph.is_match("ac"));
+        let caps = graph.captures("xabby").unwrap();
+        assert_eq!(caps.str(1), Some("b"));
+    }
+
+    #[test]
+    fn test_whole_word() {
+        let graph = RegexBuilder::new("cat").whole_word(true).build().unwrap();
+        assert_eq!(graph.find("a cat sat"), Some(Match { start: 2, end: 5 }));
+        assert_eq!(graph.find("category"), None);
+        assert_eq!(graph.find("concat"), None);
+        assert!(RegexBuilder::new("cat")
+            .build()
+            .unwrap()
+            .find("category")
+            .is_some());
+        // the whole alternation is wrapped, not just its branches
+        let graph = RegexBuilder::new("ab|c").whole_word(true).build().unwrap();
+        assert_eq!(graph.count_matches("ab abc c cab"), 2);
+        assert_eq!(
+            RegexBuilder::new("a(")
+                .whole_word(true)
+                .build()
+                .unwrap_err(),
+            RegexError::UnbalancedParen(1)
+        );
+    }
+
+    #[test]
+    fn test_anchored() {
+        let graph = RegexBuilder::new("b+").build().unwrap();
+        assert_eq!(graph.find("ab"), Some(Match { start: 1, end: 2 }));
+        let graph = RegexBuilder::new("b+").anchored(true).build().unwrap();
+        assert_eq!(graph.find("ab"), None);
+        assert_eq!(graph.find("bba"), Some(Match { start: 0, end: 2 }));
+        assert_eq!(graph.find_at("abb", 1), Some(Match { start: 1, end: 3 }));
+        // matches only follow each other back to back
+        let graph = RegexBuilder::new("ab").anchored(true).build().unwrap();
+        assert_eq!(graph.count_matches("ababxab"), 2);
+    }
+
+    #[test]
+    fn test_ascii() {
+        // ARABIC-INDIC DIGIT THREE
+        let graph = RegexBuilder::new("\\d").build().unwrap();
+        assert!(graph.is_match("3"));
+        assert!(!graph.is_match("\u{663}"));
+        let graph = RegexBuilder::new("\\d").ascii(false).build().unwrap();
+        assert!(graph.is_match("\u{663}"));
+        assert!(graph.is_match("3"));
+
+        let graph = RegexBuilder::new("\\w+\\s[[:alpha:]\\d]")
+            .ascii(false)
+            .build()
+            .unwrap();
+        assert!(graph.is_match("é_1\u{3000}ж"));
+        assert!(graph.is_match("a b"));
+        let graph = RegexBuilder::new("\\w+\\s[[:alpha:]\\d]").build().unwrap();
+        assert!(!graph.is_match("é_1\u{3000}ж"));
+        assert!(graph.is_match("a_1\t9"));
+
+        let graph = RegexBuilder::new("[^[:space:]]")
+            .ascii(false)
+            .build()
+            .unwrap();
+        assert!(graph.is_match("a"));
+        assert!(!graph.is_match("\u{3000}"));
+        // negated unions of classes and members, and shorthands in brackets
+        for ascii in [true, false] {
+            let build = |pattern: &str| RegexBuilder::new(pattern).ascii(ascii).build().unwrap();
+            let graph = build("[^[:alnum:]]");
+            assert!(graph.is_match("-") && graph.is_match(" "));
+            assert!(!graph.is_match("a") && !graph.is_match("7"));
+            assert_eq!(graph.is_match("é"), ascii);
+            let graph = build("[^\\dx]");
+            assert!(graph.is_match("y"));
+            assert!(!graph.is_match("x") && !graph.is_match("3"));
+            assert_eq!(graph.is_match("٣"), ascii);
+            let graph = build("[^a[:space:]]");
+            assert!(graph.is_match("b"));
+            assert!(!graph.is_match("a") && !graph.is_match("\t"));
+            assert_eq!(graph.is_match("\u{3000}"), ascii);
+            let graph = build("[^\\d\\s]+");
+            assert!(graph.is_match("ab"));
+            assert!(!graph.is_match("a b") && !graph.is_match("a1"));
+            let graph = build("[\\w-]+");
+            assert!(graph.is_match("a_b-9"));
+            assert!(!graph.is_match("a b"));
+            assert_eq!(graph.is_match("żółw"), !ascii);
+            let graph = build("[\\s]");
+            assert!(graph.is_match(" ") && graph.is_match("\n"));
+            assert!(!graph.is_match("a"));
+            assert!(build("[^\\s]").is_match("a"));
+            assert!(!build("[^\\s]").is_match(" "));
+        }
+    }
+
+    #[test]
+    fn test_active_state_limit() {
+        // every a+ keeps its loop alive, so the active set grows with the input
+        let pattern = "a+a+a+a+a+b";
+        let graph = RegexBuilder::new(pattern).build().unwrap();
+        assert_eq!(graph.try_is_match("aaaaab"), Ok(true));
+        let graph = RegexBuilder::new(pattern)
+            .active_state_limit(4)
+            .build()
+            .unwrap();
+        assert_eq!(graph.try_is_match("aaaaab"), Err(MatchError::TooManyStates));
+        assert_eq!(
+            graph.is_match_within("aaaaab", 100),
+            Err(MatchError::TooManyStates)
+        );
+        assert!(!graph.is_match("aaaaab"));
+    }
+
+    #[test]
+    fn test_counted_repetition() {
+        use crate::lazy_dfa::LazyDfa;
+        use crate::matcher::Matcher;
+        use crate::pike::PikeVm;
+        use crate::runner::NfaRunner;
+
+        // copies of the operand by default
+        let graph = RegexBuilder::new("a{3}").build().unwrap();
+        assert!(graph.is_match("aaa"));
+        assert!(!graph.is_match("aa") && !graph.is_match("aaaa"));
+        assert_eq!(graph.find("baaaa"), Some(Match { start: 1, end: 4 }));
+        for (pattern, accepted, rejected) in [
+            ("a{2,3}", &["aa", "aaa"][..], &["a", "aaaa"][..]),
+            ("a{2,}", &["aa", "aaaaa"], &["", "a"]),
+            ("(ab){0,2}c", &["c", "abc", "ababc"], &["abababc", "ac"]),
+            ("x{0}y", &["y"], &["xy"]),
+            ("(a|bc){2}", &["aa", "abc", "bca"], &["a", "bcbcbc"]),
+            ("a{1,2}{2}", &["aa", "aaaa"], &["a", "aaaaa"]),
+        ] {
+            let expanded = RegexBuilder::new(pattern).build().unwrap();
+            let counted = RegexBuilder::new(pattern)
+                .counted_loops(true)
+                .build()
+                .unwrap();
+            for input in accepted {
+                assert!(expanded.is_match(input), "{pattern} {input}");
+                assert!(counted.is_match(input), "{pattern} {input}");
+            }
+            for input in rejected {
+                assert!(!expanded.is_match(input), "{pattern} {input}");
+                assert!(!counted.is_match(input), "{pattern} {input}");
+            }
+        }
+        let graph = RegexBuilder::new("(\\d){3}").build().unwrap();
+        let caps = graph.captures("123").unwrap();
+        assert_eq!(caps.str(1), Some("3"));
+
+        // every matching API keeps the counters
+        for (pattern, inputs) in [
+            ("a{2,3}", &["aaa", "xaaaaay", "a", "", "aa-aaaa"][..]),
+            ("(ab){2}c?", &["ababc", "abab", "xabababc", "ab"]),
+            ("b(a{1,2}|c){2}", &["baa", "bacaab", "bcc", "b", "bac"]),
+            ("(a|b){3,}", &["abab", "ab", "xaaay", "abba-bb"]),
+            ("\\ba{2}\\b", &["aa", "aaa", "x aa"]),
+        ] {
+            let expanded = RegexBuilder::new(pattern).build().unwrap();
+            let counted = RegexBuilder::new(pattern)
+                .counted_loops(true)
+                .build()
+                .unwrap();
+            let spans = |graph: &NFAGraph, input| -> Vec<Option<Match>> {
+                graph
+                    .captures(input)
+                    .map_or(Vec::new(), |caps| caps.iter().collect())
+            };
+            for input in inputs {
+                let expected = expanded.is_match(input);
+                assert_eq!(
+                    counted.find(input),
+                    expanded.find(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(
+                    counted.find_shortest(input),
+                    expanded.find_shortest(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(
+                    counted.match_indices(input),
+                    expanded.match_indices(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(
+                    counted.find_overlapping(input),
+                    expanded.find_overlapping(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(
+                    counted.find_tagged(input),
+                    expanded.find_tagged(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(spans(&counted, input), spans(&expanded, input));
+                assert_eq!(
+                    counted.longest_prefix(input),
+                    expanded.longest_prefix(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(
+                    counted.shortest_prefix(input),
+                    expanded.shortest_prefix(input),
+                    "{pattern} {input}"
+                );
+                assert_eq!(counted.is_full_match(input), expected);
+                assert_eq!(Matcher::new(&counted).is_match(input), expected);
+                assert_eq!(PikeVm::new(&counted).is_match(input), expected);
+                assert_eq!(LazyDfa::new(&counted).is_match(input), expected);
+                let mut runner = NfaRunner::new(&counted);
+                runner.feed(input);
+                assert_eq!(runner.is_match_so_far(), expected, "{pattern} {input}");
+            }
+            assert_eq!(counted.matches_empty(), expanded.matches_empty());
+            assert_eq!(
+                counted.sample_matches(6, 20),
+                expanded.sample_matches(6, 20),
+                "{pattern}"
+            );
+        }
+
+        // a counted loop keeps the graph small
+        let counted = RegexBuilder::new("a{500}")
+            .counted_loops(true)
+            .build()
+            .unwrap();
+        assert!(counted.stats().state_count < 10);
+        assert!(counted.is_match(&"a".repeat(500)));
+        assert!(!counted.is_match(&"a".repeat(499)));
+        assert!(!counted.is_match(&"a".repeat(501)));
+        assert_eq!(counted.try_is_match(&"a".repeat(500)), Ok(true));
+        assert_eq!(
+            counted.to_dfa(),
+            Err(RegexError::Unsupported("counted loops in a DFA"))
+        );
+        let expanded = RegexBuilder::new("a{500}").build().unwrap();
+        assert!(expanded.stats().state_count >= 1000);
+        assert!(expanded.is_match(&"a".repeat(500)));
+        assert_eq!(
+            RegexBuilder::new("a{100000}").build().unwrap_err(),
+            RegexError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT)
+        );
+        // counts whose copies don't even fit in a usize
+        for pattern in [
+            "a{18446744073709551615,}",
+            "a{18446744073709551615}",
+            "(ab){9223372036854775807}",
+        ] {
+            assert_eq!(
+                RegexBuilder::new(pattern).build().unwrap_err(),
+                RegexError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT),
+                "{pattern}"
+            );
+        }
+        // the builder-less constructors are bounded by the default limit too
+        assert!(!NFAGraph::new("a{100000000}").is_valid());
+        assert_eq!(
+            NFAGraph::from_postfix("a{100000000,100000000}"),
+            Err(RegexError::SizeLimitExceeded(DEFAULT_SIZE_LIMIT))
+        );
+
+        for (pattern, error) in [
+            ("{3}", RegexError::DanglingQuantifier(0)),
+            ("a|{3}", RegexError::DanglingQuantifier(2)),
+            ("a{3,2}", RegexError::IllegalPattern),
+            ("a{,2}", RegexError::IllegalPattern),
+            ("a{2", RegexError::IllegalPattern),
+            ("a{2}?", RegexError::Unsupported("lazy counted repetition")),
+        ] {
+            assert_eq!(
+                RegexBuilder::new(pattern).build().unwrap_err(),
+                error,
+                "{pattern}"
+            );
+        }
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_unicode_normalize() {
+        use crate::lazy_dfa::LazyDfa;
+        use crate::matcher::Matcher;
+        use crate::pike::PikeVm;
+
+        // a composed é in the pattern, an e with a combining acute accent in
+        // the input
+        let decomposed = "cafe\u{301}";
+        let graph = RegexBuilder::new("caf\u{e9}")
+            .unicode_normalize(true)
+            .build()
+            .unwrap();
+        assert!(graph.is_match(decomposed));
+        assert!(graph.is_match("caf\u{e9}"));
+        assert_eq!(graph.try_is_match(decomposed), Ok(true));
+        assert!(graph.is_full_match(decomposed));
+        assert!(Matcher::new(&graph).is_match(decomposed));
+        assert!(PikeVm::new(&graph).is_match(decomposed));
+        assert!(LazyDfa::new(&graph).is_match(decomposed));
+        let graph = RegexBuilder::new("caf\u{e9}").build().unwrap();
+        assert!(!graph.is_match(decomposed));
+        assert!(graph.is_match("caf\u{e9}"));
+
+        // literal pattern chars are normalized too
+        let graph = RegexBuilder::new(decomposed)
+            .unicode_normalize(true)
+            .build()
+            .unwrap();
+        assert!(graph.is_match("caf\u{e9}"));
+        assert!(graph.is_match(decomposed));
+    }
+}
@@ -0,0 +1,347 @@
+use std::ops::Index;
+
+use crate::nfa::{Match, NFAGraph};
+use crate::pike::PikeVm;
+
+// Where each capture group matched. Group 0 is the whole match and groups
+// that did not take part in it have no span.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'r, 't> {
+    text: &'t str,
+    spans: Vec<Option<Match>>,
+    names: &'r [Option<String>],
+}
+
+impl<'t> Captures<'_, 't> {
+    // Captures of an automaton without groups, only the whole match.
+    pub(crate) fn whole(text: &'t str, m: Match) -> Captures<'static, 't> {
+        Captures {
+            text,
+            spans: vec![Some(m)],
+            names: &[],
+        }
+    }
+
+    pub fn get(&self, i: usize) -> Option<Match> {
+        self.spans.get(i).copied().flatten()
+    }
+
+    pub fn name(&self, name: &str) -> Option<Match> {
+        let i = self.names.iter().position(|n| n.as_deref() == Some(name))?;
+        self.get(i + 1)
+    }
+
+    // Every group in order, group 0 first, `None` for those that didn't
+    // take part in the match.
+    pub fn iter(&self) -> impl Iterator<Item = Option<Match>> + '_ {
+        self.spans.iter().copied()
+    }
+
+    // Number of groups including the implicit group 0.
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    // The text group `i` matched.
+    pub fn str(&self, i: usize) -> Option<&'t str> {
+        self.get(i).map(|m| &self.text[m.start..m.end])
+    }
+
+    // Appends `template` to `dst` with `$n`, `$name`, `${n}` and `${name}`
+    // replaced by what the group matched, nothing for a group that didn't,
+    // and `$$` by a `$`. A name runs as far as letters, digits and `_` go.
+    pub fn expand(&self, template: &str, dst: &mut String) {
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            dst.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix('$') {
+                dst.push('$');
+                rest = after;
+                continue;
+            }
+            let (name, after) = match rest.strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) => (&braced[..end], &braced[end + 1..]),
+                    None => ("", rest),
+                },
+                None => {
+                    let end = rest
+                        .find(|c: char| !c.is_alphanumeric() && c != '_')
+                        .unwrap_or(rest.len());
+                    (&rest[..end], &rest[end..])
+                }
+            };
+            if name.is_empty() {
+                // not a reference, keep the `$`
+                dst.push('$');
+                continue;
+            }
+            let span = match name.parse::<usize>() {
+                Ok(i) => self.get(i),
+                Err(_) => self.name(name),
+            };
+            if let Some(m) = span {
+                dst.push_str(&self.text[m.start..m.end]);
+            }
+            rest = after;
+        }
+        dst.push_str(rest);
+    }
+}
+
+// `caps[i]` is the text of group `i` and panics if it didn't match.
+impl Index<usize> for Captures<'_, '_> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.str(i)
+            .unwrap_or_else(|| panic!("group {} did not match", i))
+    }
+}
+
+impl NFAGraph {
+    // The leftmost-longest match with the span of every group. The overall
+    // span comes from `find`, and `PikeVm` assigns the groups within it, so
+    // they are leftmost-longest as well: lazy quantifiers first repeat as
+    // little as they can, then each group, in order, starts as early and
+    // ends as late as the groups before it allow, settled wherever two paths
+    // through the graph meet. Repeated groups report their last iteration.
+    // This takes time linear in the length of the match.
+    pub fn captures<'t>(&self, s: &'t str) -> Option<Captures<'_, 't>> {
+        self.captures_at(s, self.find(s)?)
+    }
+
+    // Replaces every non-overlapping match with what `f` makes of its
+    // captures, keeping the text in between.
+    pub fn replace_with<F>(&self, s: &str, mut f: F) -> String
+    where
+        F: FnMut(&Captures) -> String,
+    {
+        let mut out = String::with_capacity(s.len());
+        let mut last = 0;
+        for m in self.find_iter(s) {
+            let caps = self.captures_at(s, m).unwrap();
+            out.push_str(&s[last..m.start]);
+            out.push_str(&f(&caps));
+            last = m.end;
+        }
+        out.push_str(&s[last..]);
+        out
+    }
+
+    // Replaces every non-overlapping match with `template`, expanded as in
+    // `Captures::expand`.
+    pub fn replace_all(&self, s: &str, template: &str) -> String {
+        self.replace_with(s, |caps| {
+            let mut out = String::new();
+            caps.expand(template, &mut out);
+            out
+        })
+    }
+
+    // Replaces only the leftmost match with `template`, expanded as in
+    // `Captures::expand`. Without a match `s` comes back unchanged.
+    pub fn replace_first(&self, s: &str, template: &str) -> String {
+        let Some((m, caps)) = self
+            .find(s)
+            .and_then(|m| Some((m, self.captures_at(s, m)?)))
+        else {
+            return s.to_string();
+        };
+        let mut out = String::with_capacity(s.len());
+        out.push_str(&s[..m.start]);
+        caps.expand(template, &mut out);
+        out.push_str(&s[m.end..]);
+        out
+    }
+
+    // The group spans for a match `m` that `find` reported.
+    fn captures_at<'t>(&self, s: &'t str, m: Match) -> Option<Captures<'_, 't>> {
+        let slots = PikeVm::new(self).slots(s, m)?;
+        let spans = slots
+            .chunks(2)
+            .map(|pair| match (pair[0], pair[1]) {
+                (Some(start), Some(end)) => Some(Match { start, end }),
+                _ => None,
+            })
+            .collect();
+        Some(Captures {
+            text: s,
+            spans,
+            names: &self.group_names,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::RegexBuilder;
+    use crate::error::RegexError;
+    use crate::nfa::{Match, NFAGraph};
+
+    #[test]
+    fn test_captures() {
+        let graph = NFAGraph::new("(?P<year>\\d+)-(?P<month>\\d+)");
+        let caps = graph.captures("on 2024-01").unwrap();
+        assert_eq!(caps.len(), 3);
+        assert_eq!(caps.get(0), Some(Match { start: 3, end: 10 }));
+        assert_eq!(caps.name("year"), Some(Match { start: 3, end: 7 }));
+        assert_eq!(caps.name("month"), Some(Match { start: 8, end: 10 }));
+        assert_eq!(caps.get(2), caps.name("month"));
+        assert_eq!(caps.name("day"), None);
+
+        let graph = NFAGraph::new("(a)|(?<b>b)");
+        let caps = graph.captures("b").unwrap();
+        assert_eq!(caps.get(1), None);
+        assert_eq!(caps.name("b"), Some(Match { start: 0, end: 1 }));
+
+        // the last iteration of a loop wins
+        let graph = NFAGraph::new("(a|b)+");
+        let caps = graph.captures("abb").unwrap();
+        assert_eq!(caps.get(1), Some(Match { start: 2, end: 3 }));
+    }
+
+    #[test]
+    fn test_repeated_groups() {
+        let graph = NFAGraph::new("x(ab)+");
+        let caps = graph.captures("xababab").unwrap();
+        assert_eq!(caps.get(1), Some(Match { start: 5, end: 7 }));
+        // each replacement reads the groups of its own match
+        let graph = NFAGraph::new("((a)|(b))+");
+        assert_eq!(graph.replace_all("ab-ba-aa", "[$2$3]"), "[ab]-[ab]-[a]");
+        assert_eq!(
+            NFAGraph::new("(ab)*c").replace_all("ababc c", "<$1>"),
+            "<ab> <>"
+        );
+    }
+
+    #[test]
+    fn test_iter() {
+        let graph = NFAGraph::new("(a)(x)?(b)");
+        let text = "cab";
+        let caps = graph.captures(text).unwrap();
+        let groups: Vec<Option<&str>> = caps.iter().map(|m| m.map(|m| m.as_str(text))).collect();
+        assert_eq!(groups, vec![Some("ab"), Some("a"), None, Some("b")]);
+        assert_eq!(caps.iter().count(), caps.len());
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let graph = NFAGraph::new("\\d+");
+        let doubled = graph.replace_with("3 apples, 21 pears", |caps| {
+            (caps[0].parse::<u32>().unwrap() * 2).to_string()
+        });
+        assert_eq!(doubled, "6 apples, 42 pears");
+
+        let graph = NFAGraph::new("(?P<key>[a-z]+)=(?P<value>[a-z]+)");
+        let swapped = graph.replace_with("a=b, cd=ef", |caps| {
+            format!("{}={}", &caps[2], caps.str(1).unwrap().to_uppercase())
+        });
+        assert_eq!(swapped, "b=A, ef=CD");
+        assert_eq!(graph.replace_with("none", |_| String::new()), "none");
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let graph = NFAGraph::new("(?P<y>\\d+)-(\\d+)");
+        assert_eq!(
+            graph.replace_all("2024-01, 1999-12", "$2/${y}"),
+            "01/2024, 12/1999"
+        );
+        assert_eq!(graph.replace_all("1-2", "$$1 $9 $ ${1"), "$1  $ ${1");
+        assert_eq!(graph.replace_all("1-2", "${2}0"), "20");
+    }
+
+    #[test]
+    fn test_replace_first() {
+        let graph = NFAGraph::new("\\d+");
+        assert_eq!(graph.replace_first("a1b2", "#"), "a#b2");
+        assert_eq!(graph.replace_first("ab", "#"), "ab");
+        let graph = NFAGraph::new("(\\w+)@(\\w+)");
+        assert_eq!(graph.replace_first("x@y z@w", "$2@$1"), "y@x z@w");
+    }
+
+    #[test]
+    fn test_posix_submatches() {
+        let graph = NFAGraph::new("a|ab");
+        assert_eq!(graph.find("ab"), Some(Match { start: 0, end: 2 }));
+        let graph = NFAGraph::new("(a|ab)");
+        let caps = graph.captures("ab").unwrap();
+        assert_eq!(caps.str(1), Some("ab"));
+        // the first group takes as much as it can, even from a greedy later one
+        let graph = NFAGraph::new("(a|ab)(b*)");
+        let caps = graph.captures("ab").unwrap();
+        assert_eq!((caps.str(1), caps.str(2)), (Some("ab"), Some("")));
+        let graph = NFAGraph::new("(a*)(ab)?b*");
+        let caps = graph.captures("aab").unwrap();
+        assert_eq!((caps.str(1), caps.get(2)), (Some("aa"), None));
+        // lazy quantifiers still repeat as little as they can
+        let graph = NFAGraph::new("(a*?)(a*)");
+        let caps = graph.captures("aa").unwrap();
+        assert_eq!((caps.str(1), caps.str(2)), (Some(""), Some("aa")));
+        let graph = NFAGraph::new("<(.+?)>");
+        let caps = graph.captures("<a><b>").unwrap();
+        assert_eq!((caps.str(0), caps.str(1)), (Some("<a>"), Some("a")));
+    }
+
+    #[test]
+    fn test_long_matches() {
+        // far too many ways to split the a's to try them one by one
+        let graph = NFAGraph::new("((a|aa)*)*b");
+        let text = format!("{}b", "a".repeat(5000));
+        let caps = graph.captures(&text).unwrap();
+        assert_eq!(
+            caps.get(0),
+            Some(Match {
+                start: 0,
+                end: 5001
+            })
+        );
+        assert_eq!(caps.str(1), Some(&text[..5000]));
+        // the same groups however long the match runs on
+        let graph = NFAGraph::new("(a|ab)(c|bcd)(d*)");
+        for len in [0, 10, 10_000] {
+            let text = format!("abcd{}", "d".repeat(len));
+            let caps = graph.captures(&text).unwrap();
+            assert_eq!((caps.str(1), caps.str(2)), (Some("ab"), Some("c")));
+            assert_eq!(caps.str(3), Some(&text[3..]));
+        }
+    }
+
+    #[test]
+    fn test_non_capturing() {
+        let graph = NFAGraph::new("(?:a)(b)");
+        assert_eq!(graph.capture_count(), 1);
+        let caps = graph.captures("ab").unwrap();
+        assert_eq!(caps.len(), 2);
+        assert_eq!(caps.get(1), Some(Match { start: 1, end: 2 }));
+
+        let graph = NFAGraph::new("(?:ab)+c");
+        assert_eq!(graph.capture_count(), 0);
+        assert!(graph.is_match("ababc"));
+        assert!(!graph.is_match("abac"));
+        assert_eq!(crate::nfa::re2post("(?:ab)+"), crate::nfa::re2post("(ab)+"));
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        for pattern in [
+            "(?P<>a)",
+            "(?P<1a>a)",
+            "(?P<a>a)(?P<a>b)",
+            "(?P<a-b>a)",
+            "(?Pa)",
+        ] {
+            assert_eq!(
+                RegexBuilder::new(pattern).build().unwrap_err(),
+                RegexError::IllegalPattern,
+                "{pattern}"
+            );
+        }
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::error::{DeserializeError, RegexError};
+use crate::nfa::{Context, Match, NFAGraph, StateId, Transition};
+
+// The version byte `DFA::to_bytes` starts with, bumped whenever the layout
+// changes.
+const FORMAT_VERSION: u8 = 1;
+
+// A deterministic automaton over chars. The alphabet lists every char that
+// appears on some edge of the source graph; all other chars share the extra
+// last column of the transition table, which only a wildcard can take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DFA {
+    pub alphabet: Vec<char>,
+    pub transitions: Vec<Vec<Option<usize>>>,
+    pub accepting: Vec<bool>,
+    pub start: usize,
+}
+
+impl NFAGraph {
+    // Subset construction. Assertions depend on the surrounding input and
+    // bytes mode uses a different alphabet, so neither can be determinized.
+    pub fn to_dfa(&self) -> Result<DFA, RegexError> {
+        if !self.valid {
+            return Err(RegexError::IllegalPattern);
+        }
+        let mut alphabet = BTreeSet::new();
+        for state in self.states.values() {
+            for transition in state.outs.values() {
+                match transition {
+                    Transition::Char(chars) | Transition::NotChar(chars) => {
+                        alphabet.extend(chars.iter().copied())
+                    }
+                    Transition::Assert(_) => {
+                        return Err(RegexError::Unsupported("assertions in a DFA"))
+                    }
+                    Transition::Byte(_) => return Err(RegexError::Unsupported("bytes in a DFA")),
+                    Transition::Counter(_) => {
+                        return Err(RegexError::Unsupported("counted loops in a DFA"))
+                    }
+                    Transition::Class(_) | Transition::NotClass(..) => {
+                        return Err(RegexError::Unsupported("Unicode classes in a DFA"))
+                    }
+                    // a wildcard that skips the line terminator needs its
+                    // own columns for it
+                    Transition::Any if !self.dot_matches_newline => {
+                        alphabet.extend(
+                            ['\n', '\r']
+                                .into_iter()
+                                .filter(|c| self.line_terminator.contains(*c)),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let alphabet: Vec<char> = alphabet.into_iter().collect();
+
+        let key = |set: &[StateId]| set.iter().copied().collect::<BTreeSet<StateId>>();
+        let start = self.closure(vec![self.start], Context::default());
+        let mut ids: HashMap<BTreeSet<StateId>, usize> = HashMap::new();
+        let mut sets = vec![start.clone()];
+        ids.insert(key(&start), 0);
+        let mut transitions = Vec::new();
+        let mut queue = VecDeque::from([0]);
+        while let Some(id) = queue.pop_front() {
+            let set = sets[id].clone();
+            let mut row = Vec::with_capacity(alphabet.len() + 1);
+            for column in 0..=alphabet.len() {
+                let moved = match alphabet.get(column) {
+                    Some(c) => self.move2(*c, &set),
+                    None => self.move_any(&set),
+                };
+                if moved.is_empty() {
+                    row.push(None);
+                    continue;
+                }
+                let next = self.closure(moved, Context::default());
+                let next_id = *ids.entry(key(&next)).or_insert_with(|| {
+                    sets.push(next);
+                    queue.push_back(sets.len() - 1);
+                    sets.len() - 1
+                });
+                row.push(Some(next_id));
+            }
+            if transitions.len() <= id {
+                transitions.resize(id + 1, Vec::new());
+            }
+            transitions[id] = row;
+        }
+        let accepting = sets
+            .iter()
+            .map(|set| set.iter().any(|id| self.is_accepting(id)))
+            .collect();
+        Ok(DFA {
+            alphabet,
+            transitions,
+            accepting,
+            start: 0,
+        })
+    }
+
+    // Whether both graphs accept the same language, decided on their DFAs.
+    // A graph that can't be determinized is only known to be equivalent to
+    // an identical graph, so anything else conservatively reports false.
+    pub fn equivalent(&self, other: &NFAGraph) -> bool {
+        match (self.to_dfa(), other.to_dfa()) {
+            (Ok(a), Ok(b)) => a.equivalent(&b),
+            _ => self == other,
+        }
+    }
+
+    // Targets of the wildcard edges, i.e. where a char outside the alphabet
+    // can go. The line terminator is in the alphabet whenever the wildcard
+    // treats it specially, so it never ends up here.
+    fn move_any(&self, current_set: &[StateId]) -> Vec<StateId> {
+        let mut next_set = Vec::new();
+        for state_id in current_set.iter() {
+            let state = self.states.get(state_id).unwrap();
+            for out in state.outs.iter() {
+                // a negated class lists only alphabet chars, so it takes
+                // everything else
+                if let Transition::Any | Transition::NotChar(_) = out.1 {
+                    next_set.push(*out.0);
+                }
+            }
+        }
+        next_set
+    }
+}
+
+impl DFA {
+    pub fn state_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    fn column(&self, c: char) -> usize {
+        self.alphabet
+            .binary_search(&c)
+            .unwrap_or(self.alphabet.len())
+    }
+
+    // Full match, like `NFAGraph::is_match`.
+    pub fn is_match(&self, s: &str) -> bool {
+        let mut state = self.start;
+        for c in s.chars() {
+            match self.transitions[state][self.column(c)] {
+                Some(next) => state = next,
+                None => return false,
+            }
+        }
+        self.accepting[state]
+    }
+
+    // Walks both automata in lockstep over the union of their alphabets,
+    // with `None` as the dead state, looking for a pair of states that
+    // disagree on acceptance.
+    pub fn equivalent(&self, other: &DFA) -> bool {
+        let alphabet: BTreeSet<char> = self
+            .alphabet
+            .iter()
+            .chain(other.alphabet.iter())
+            .copied()
+            .collect();
+        // a char outside both alphabets takes the last column of each
+        let columns: Vec<(usize, usize)> = alphabet
+            .iter()
+            .map(|c| (self.column(*c), other.column(*c)))
+            .chain([(self.alphabet.len(), other.alphabet.len())])
+            .collect();
+        let accepting = |dfa: &DFA, q: Option<usize>| q.is_some_and(|q| dfa.accepting[q]);
+        let start = (Some(self.start), Some(other.start));
+        let mut seen = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some((p, q)) = queue.pop_front() {
+            if accepting(self, p) != accepting(other, q) {
+                return false;
+            }
+            for (a, b) in columns.iter() {
+                let next = (
+                    p.and_then(|p| self.transitions[p][*a]),
+                    q.and_then(|q| other.transitions[q][*b]),
+                );
+                if next != (None, None) && seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        true
+    }
+
+    // Leftmost-longest match at or after byte offset `start`, `None` when
+    // `start` is past the end or inside a char. Lazy quantifiers don't
+    // survive determinization, so they match as much as greedy ones here.
+    //
+    // A single forward scan: a thread starts at every offset until some
+    // thread accepts, and threads that reach the same state merge into the
+    // one that started first, as the rest of the input treats them alike.
+    // Once a thread accepts, the threads that started after it can't win.
+    pub fn find_at(&self, s: &str, start: usize) -> Option<Match> {
+        if start > s.len() || !s.is_char_boundary(start) {
+            return None;
+        }
+        // (state, start) ordered by start
+        let mut threads: Vec<(usize, usize)> = Vec::new();
+        let mut best: Option<Match> = None;
+        let mut chars = s[start..].char_indices().map(|(i, c)| (start + i, c));
+        let mut pos = start;
+        let mut seen = vec![false; self.state_count()];
+        loop {
+            if best.is_none() && threads.iter().all(|(state, _)| *state != self.start) {
+                threads.push((self.start, pos));
+            }
+            if let Some(i) = threads.iter().position(|(state, _)| self.accepting[*state]) {
+                best = Some(Match {
+                    start: threads[i].1,
+                    end: pos,
+                });
+                threads.truncate(i + 1);
+            }
+            let Some((at, c)) = chars.next() else {
+                return best;
+            };
+            let column = self.column(c);
+            let mut next: Vec<(usize, usize)> = Vec::with_capacity(threads.len());
+            for (state, from) in threads {
+                if let Some(target) = self.transitions[state][column] {
+                    if !seen[target] {
+                        seen[target] = true;
+                        next.push((target, from));
+                    }
+                }
+            }
+            for (state, _) in next.iter() {
+                seen[*state] = false;
+            }
+            threads = next;
+            pos = at + c.len_utf8();
+            if threads.is_empty() && best.is_some() {
+                return best;
+            }
+        }
+    }
+
+    // Hopcroft's partition refinement. States that can't reach an accepting
+    // state end up with the implicit dead state and are dropped.
+    pub fn minimize(&self) -> DFA {
+        let n = self.state_count();
+        let columns = self.alphabet.len() + 1;
+        // complete the automaton with an explicit dead state `n`
+        let delta = |q: usize, column: usize| -> usize {
+            if q == n {
+                n
+            } else {
+                self.transitions[q][column].unwrap_or(n)
+            }
+        };
+        let (accepting, rejecting): (Vec<usize>, Vec<usize>) =
+            (0..=n).partition(|q| *q < n && self.accepting[*q]);
+        let mut partition: Vec<Vec<usize>> = [accepting, rejecting]
+            .into_iter()
+            .filter(|block| !block.is_empty())
+            .collect();
+        let mut work = partition.clone();
+        while let Some(splitter) = work.pop() {
+            for column in 0..columns {
+                let into: BTreeSet<usize> = (0..=n)
+                    .filter(|q| splitter.binary_search(&delta(*q, column)).is_ok())
+                    .collect();
+                let mut refined = Vec::with_capacity(partition.len());
+                for block in partition.drain(..) {
+                    let (inside, outside): (Vec<usize>, Vec<usize>) =
+                        block.iter().copied().partition(|q| into.contains(q));
+                    if inside.is_empty() || outside.is_empty() {
+                        refined.push(block);
+                        continue;
+                    }
+                    if let Some(pos) = work.iter().position(|w| *w == block) {
+                        work.swap_remove(pos);
+                        work.push(inside.clone());
+                        work.push(outside.clone());
+                    } else if inside.len() <= outside.len() {
+                        work.push(inside.clone());
+                    } else {
+                        work.push(outside.clone());
+                    }
+                    refined.push(inside);
+                    refined.push(outside);
+                }
+                partition = refined;
+            }
+        }
+
+        let mut block_of = vec![0; n + 1];
+        for (i, block) in partition.iter().enumerate() {
+            for q in block {
+                block_of[*q] = i;
+            }
+        }
+        let dead = block_of[n];
+        // renumber the surviving blocks in breadth-first order from the start
+        let mut ids: HashMap<usize, usize> = HashMap::new();
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+        if block_of[self.start] != dead {
+            ids.insert(block_of[self.start], 0);
+            order.push(block_of[self.start]);
+            queue.push_back(block_of[self.start]);
+        }
+        while let Some(block) = queue.pop_front() {
+            let q = partition[block][0];
+            for column in 0..columns {
+                let next = block_of[delta(q, column)];
+                if next != dead && !ids.contains_key(&next) {
+                    ids.insert(next, order.len());
+                    order.push(next);
+                    queue.push_back(next);
+                }
+            }
+        }
+        if order.is_empty() {
+            // nothing is accepted: a single rejecting state
+            return DFA {
+                alphabet: self.alphabet.clone(),
+                transitions: vec![vec![None; columns]],
+                accepting: vec![false],
+                start: 0,
+            };
+        }
+        let transitions = order
+            .iter()
+            .map(|block| {
+                let q = partition[*block][0];
+                (0..columns)
+                    .map(|column| ids.get(&block_of[delta(q, column)]).copied())
+                    .collect()
+            })
+            .collect();
+        let accepting = order
+            .iter()
+            .map(|block| self.accepting[partition[*block][0]])
+            .collect();
+        DFA {
+            alphabet: self.alphabet.clone(),
+            transitions,
+            accepting,
+            start: 0,
+        }
+    }
+
+    // The automaton matching exactly the strings this one rejects. The
+    // alphabet is complete already, as the last column stands for every
+    // char outside of it, so completing the automaton only takes a dead
+    // state for the missing edges, which then accepts like every other
+    // rejecting state.
+    pub fn complement(&self) -> DFA {
+        let dead = self.state_count();
+        let columns = self.alphabet.len() + 1;
+        let mut transitions: Vec<Vec<Option<usize>>> = self
+            .transitions
+            .iter()
+            .map(|row| row.iter().map(|next| Some(next.unwrap_or(dead))).collect())
+            .collect();
+        transitions.push(vec![Some(dead); columns]);
+        let accepting = self
+            .accepting
+            .iter()
+            .map(|accepting| !accepting)
+            .chain([true])
+            .collect();
+        DFA {
+            alphabet: self.alphabet.clone(),
+            transitions,
+            accepting,
+            start: self.start,
+        }
+    }
+
+    // The transition table as plain data for embedding: row `q` column `i`
+    // is the next state on `alphabet[i]`, the last column is for every other
+    // char. Missing edges point at an extra dead state, the last row, which
+    // loops on itself and doesn't accept.
+    pub fn table(&self) -> Vec<Vec<usize>> {
+        let dead = self.state_count();
+        let columns = self.alphabet.len() + 1;
+        self.transitions
+            .iter()
+            .map(|row| row.iter().map(|next| next.unwrap_or(dead)).collect())
+            .chain([vec![dead; columns]])
+            .collect()
+    }
+
+    // A compact binary form for embedding, loaded back by `from_bytes`. The
+    // layout is a version byte, then little-endian u32s: the alphabet length
+    // and chars, the state count and the start state. The accepting bitset
+    // follows, one bit per state from the low bit of the first byte on, and
+    // then the transition table row by row, with u32::MAX for a missing
+    // edge.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![FORMAT_VERSION];
+        let put = |v: u32, out: &mut Vec<u8>| out.extend_from_slice(&v.to_le_bytes());
+        put(self.alphabet.len() as u32, &mut out);
+        for c in self.alphabet.iter() {
+            put(*c as u32, &mut out);
+        }
+        put(self.state_count() as u32, &mut out);
+        put(self.start as u32, &mut out);
+        let mut bits = vec![0u8; self.state_count().div_ceil(8)];
+        for (q, accepting) in self.accepting.iter().enumerate() {
+            if *accepting {
+                bits[q / 8] |= 1 << (q % 8);
+            }
+        }
+        out.extend_from_slice(&bits);
+        for row in self.transitions.iter() {
+            for next in row.iter() {
+                put(next.map_or(u32::MAX, |q| q as u32), &mut out);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<DFA, DeserializeError> {
+        let (version, data) = data.split_first().ok_or(DeserializeError::Truncated)?;
+        if *version != FORMAT_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(*version));
+        }
+        let mut pos = 0;
+        let get = |pos: &mut usize| -> Result<u32, DeserializeError> {
+            let bytes = take(data, pos, 4)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+        let alphabet_len = get(&mut pos)? as usize;
+        let mut alphabet = Vec::new();
+        for _ in 0..alphabet_len {
+            let c = get(&mut pos)?;
+            let c = char::from_u32(c).ok_or(DeserializeError::InvalidChar(c))?;
+            if alphabet.last().is_some_and(|last| *last >= c) {
+                return Err(DeserializeError::UnsortedAlphabet(c));
+            }
+            alphabet.push(c);
+        }
+        let state_count = get(&mut pos)?;
+        let start = get(&mut pos)?;
+        if start >= state_count {
+            return Err(DeserializeError::InvalidState(start));
+        }
+        let state_count = state_count as usize;
+        let bits = take(data, &mut pos, state_count.div_ceil(8))?.to_vec();
+        let accepting = (0..state_count)
+            .map(|q| bits[q / 8] & (1 << (q % 8)) != 0)
+            .collect();
+        let mut transitions = Vec::new();
+        for _ in 0..state_count {
+            let mut row = Vec::with_capacity(alphabet_len + 1);
+            for _ in 0..=alphabet_len {
+                let next = get(&mut pos)?;
+                if next == u32::MAX {
+                    row.push(None);
+                } else if (next as usize) < state_count {
+                    row.push(Some(next as usize));
+                } else {
+                    return Err(DeserializeError::InvalidState(next));
+                }
+            }
+            transitions.push(row);
+        }
+        if pos != data.len() {
+            return Err(DeserializeError::TrailingBytes);
+        }
+        Ok(DFA {
+            alphabet,
+            transitions,
+            accepting,
+            start: start as usize,
+        })
+    }
+}
+
+// The next `len` bytes of `data` from `pos` on, moving `pos` past them.
+fn take<'d>(data: &'d [u8], pos: &mut usize, len: usize) -> Result<&'d [u8], DeserializeError> {
+    let bytes = data
+        .get(*pos..*pos + len)
+        .ok_or(DeserializeError::Truncated)?;
+    *pos += len;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DFA;
+    use crate::builder::RegexBuilder;
+    use crate::error::{DeserializeError, RegexError};
+    use crate::nfa::{Match, NFAGraph};
+
+    #[test]
+    fn test_to_dfa() {
+        for pattern in [
+            "a+b+",
+            "a(b|c)*",
+            "ab?c",
+            "a.b",
+            "(a|b)*abb",
+            "a[^bc]b",
+            "[a-c]+",
+        ] {
+            let mut graph = NFAGraph::new(pattern);
+            for dot_matches_newline in [false, true] {
+                graph.dot_matches_newline = dot_matches_newline;
+                let dfa = graph.to_dfa().unwrap();
+                for input in [
+                    "aabb", "abcbc", "ac", "abc", "axb", "a\u{e9}b", "ababb", "ab", "a\nb",
+                ] {
+                    assert_eq!(
+                        dfa.is_match(input),
+                        graph.is_match(input),
+                        "{pattern} {input}"
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            NFAGraph::new("\\bab").to_dfa(),
+            Err(RegexError::Unsupported("assertions in a DFA"))
+        );
+    }
+
+    #[test]
+    fn test_minimize() {
+        let graph = NFAGraph::new("(a|b)*abb");
+        let dfa = graph.to_dfa().unwrap();
+        let minimal = dfa.minimize();
+        // the textbook minimal DFA for (a|b)*abb has four states
+        assert_eq!(minimal.state_count(), 4);
+        for input in ["abb", "aabb", "babb", "ab", "abba", "bbbabb"] {
+            assert_eq!(minimal.is_match(input), dfa.is_match(input));
+        }
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let equivalent = |a: &str, b: &str| NFAGraph::new(a).equivalent(&NFAGraph::new(b));
+        assert!(equivalent("a+", "aa*"));
+        assert!(!equivalent("a+", "a*"));
+        assert!(equivalent("(a|b)*", "(a*b*)*"));
+        assert!(equivalent("a.", "a(.|b)"));
+        assert!(!equivalent("a.", "a[^b]"));
+        assert!(!equivalent("ab", "abc"));
+        // assertions can't be determinized, so only identical graphs pass
+        assert!(equivalent("\\ba", "\\ba"));
+        assert!(!equivalent("\\ba", "\\b(a)"));
+    }
+
+    #[test]
+    fn test_find_at() {
+        for pattern in ["a+b|c", "abcd|bc|c", "(a|ab)(c|bcd)", "x*", "é+.|b"] {
+            let graph = NFAGraph::new(pattern);
+            let dfa = graph.to_dfa().unwrap();
+            for input in ["xaab", "xab c", "cab", "aa", "", "abcd", "xabcx", "aéébé"] {
+                for start in (0..=input.len()).filter(|i| input.is_char_boundary(*i)) {
+                    assert_eq!(
+                        dfa.find_at(input, start),
+                        graph.find_at(input, start),
+                        "{pattern} {input} {start}"
+                    );
+                }
+            }
+        }
+        // offsets past the end or inside a char find nothing
+        let dfa = NFAGraph::new("a*").to_dfa().unwrap();
+        assert_eq!(dfa.find_at("ab", 3), None);
+        assert_eq!(dfa.find_at("éa", 1), None);
+        assert_eq!(dfa.find_at("ab", 2), Some(Match { start: 2, end: 2 }));
+    }
+
+    #[test]
+    fn test_complement() {
+        let dfa = NFAGraph::new("a+").to_dfa().unwrap();
+        let complement = dfa.complement();
+        assert!(complement.is_match("b"));
+        assert!(complement.is_match(""));
+        assert!(complement.is_match("ab"));
+        assert!(!complement.is_match("aa"));
+        assert!(!complement.is_match("a"));
+        for input in ["", "a", "b", "aab", "ba", "xyz", "a\n"] {
+            assert_ne!(complement.is_match(input), dfa.is_match(input), "{input}");
+            assert_eq!(complement.complement().is_match(input), dfa.is_match(input));
+        }
+        // every string that is empty or has a char other than `a`
+        let others = RegexBuilder::new("([^a].*)?|a+[^a].*")
+            .dot_matches_newline(true)
+            .build()
+            .unwrap();
+        assert!(complement.equivalent(&others.to_dfa().unwrap()));
+    }
+
+    #[test]
+    fn test_table() {
+        let dfa = NFAGraph::new("(a|a)").to_dfa().unwrap();
+        let minimal = dfa.minimize();
+        assert!(minimal.state_count() <= dfa.state_count());
+        assert_eq!(minimal.state_count(), 2);
+        assert!(minimal.is_match("a"));
+        assert!(!minimal.is_match("aa"));
+
+        // walk the exported table by hand
+        let table = minimal.table();
+        assert_eq!(table.len(), 3);
+        let accepts = |s: &str| {
+            let mut q = minimal.start;
+            for c in s.chars() {
+                q = table[q][minimal.column(c)];
+            }
+            q < minimal.state_count() && minimal.accepting[q]
+        };
+        for input in ["a", "", "aa", "b", "ab"] {
+            assert_eq!(accepts(input), minimal.is_match(input), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let dfa = NFAGraph::new("a(b|c)*.").to_dfa().unwrap().minimize();
+        let bytes = dfa.to_bytes();
+        let loaded = DFA::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded, dfa);
+        for input in ["ab", "abcbx", "a", "ax\u{e9}", "bb", ""] {
+            assert_eq!(loaded.is_match(input), dfa.is_match(input), "{input}");
+        }
+        for len in 0..bytes.len() {
+            assert_eq!(
+                DFA::from_bytes(&bytes[..len]),
+                Err(DeserializeError::Truncated)
+            );
+        }
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert_eq!(
+            DFA::from_bytes(&extended),
+            Err(DeserializeError::TrailingBytes)
+        );
+        let mut other = bytes.clone();
+        other[0] = 2;
+        assert_eq!(
+            DFA::from_bytes(&other),
+            Err(DeserializeError::UnsupportedVersion(2))
+        );
+        // the start state, after the version, the alphabet and the count
+        let at = 1 + 4 * (dfa.alphabet.len() + 2);
+        let mut other = bytes.clone();
+        other[at..at + 4].copy_from_slice(&99u32.to_le_bytes());
+        assert_eq!(
+            DFA::from_bytes(&other),
+            Err(DeserializeError::InvalidState(99))
+        );
+        // `column` looks chars up by binary search, so the alphabet has to
+        // be strictly increasing
+        assert_eq!(dfa.alphabet, ['\n', 'a', 'b', 'c']);
+        let mut other = bytes.clone();
+        other[9..13].copy_from_slice(&u32::from('b').to_le_bytes());
+        other[13..17].copy_from_slice(&u32::from('a').to_le_bytes());
+        assert_eq!(
+            DFA::from_bytes(&other),
+            Err(DeserializeError::UnsortedAlphabet('a'))
+        );
+        let mut other = bytes.clone();
+        other[13..17].copy_from_slice(&u32::from('a').to_le_bytes());
+        assert_eq!(
+            DFA::from_bytes(&other),
+            Err(DeserializeError::UnsortedAlphabet('a'))
+        );
+        assert_eq!(
+            DeserializeError::UnsortedAlphabet('a').to_string(),
+            "alphabet char 'a' out of order"
+        );
+    }
+}
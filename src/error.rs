@@ -0,0 +1,124 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegexError {
+    IllegalPattern,
+    // a `*`, `+` or `?` with nothing before it to repeat, at this byte offset
+    DanglingQuantifier(usize),
+    // a `(` that is never closed or a `)` that closes nothing, at this byte
+    // offset
+    UnbalancedParen(usize),
+    // a flag in `(?flags:...)` other than `i`
+    UnknownFlag(char),
+    // postfix handed to `NFAGraph::from_postfix` is missing operands or
+    // contains unknown symbols
+    MalformedPostfix,
+    SizeLimitExceeded(usize),
+    Unsupported(&'static str),
+    InvalidPrecompiled,
+    VersionMismatch,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegexError::IllegalPattern => write!(f, "illegal pattern"),
+            RegexError::DanglingQuantifier(at) => {
+                write!(f, "quantifier with nothing to repeat at offset {}", at)
+            }
+            RegexError::UnbalancedParen(at) => write!(f, "unbalanced parenthesis at offset {}", at),
+            RegexError::UnknownFlag(c) => write!(f, "unknown flag `{}`", c),
+            RegexError::MalformedPostfix => write!(f, "malformed postfix"),
+            RegexError::SizeLimitExceeded(limit) => {
+                write!(
+                    f,
+                    "compiled pattern exceeds the size limit of {} states",
+                    limit
+                )
+            }
+            RegexError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            RegexError::InvalidPrecompiled => write!(f, "invalid precompiled regex"),
+            RegexError::VersionMismatch => {
+                write!(f, "precompiled regex is from another crate version")
+            }
+        }
+    }
+}
+
+impl Error for RegexError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchError {
+    StepLimitExceeded,
+    InvalidGraph,
+    TooManyStates,
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::StepLimitExceeded => write!(f, "step limit exceeded"),
+            MatchError::InvalidGraph => write!(f, "graph is invalid"),
+            MatchError::TooManyStates => write!(f, "too many active states"),
+        }
+    }
+}
+
+impl Error for MatchError {}
+
+// Why `DFA::from_bytes` rejected its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    // the data ends before the layout does
+    Truncated,
+    // the layout version the data starts with, which isn't this one
+    UnsupportedVersion(u8),
+    // a code point in the alphabet that isn't a char
+    InvalidChar(u32),
+    // an alphabet char not greater than the one before it, which the
+    // binary search over the alphabet can't handle
+    UnsortedAlphabet(char),
+    // a start state or transition target past the state count
+    InvalidState(u32),
+    // bytes left over after the transition table
+    TrailingBytes,
+}
+
+impl fmt::Display for DeserializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeserializeError::Truncated => write!(f, "serialized DFA is truncated"),
+            DeserializeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported serialized DFA version {}", v)
+            }
+            DeserializeError::InvalidChar(c) => write!(f, "invalid char {:#x} in alphabet", c),
+            DeserializeError::UnsortedAlphabet(c) => {
+                write!(f, "alphabet char {:?} out of order", c)
+            }
+            DeserializeError::InvalidState(q) => write!(f, "state {} out of range", q),
+            DeserializeError::TrailingBytes => write!(f, "trailing bytes after serialized DFA"),
+        }
+    }
+}
+
+impl Error for DeserializeError {}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::RegexError;
+    use crate::regex::Regex;
+
+    #[test]
+    fn test_display() {
+        fn compile(pattern: &str) -> Result<Regex, Box<dyn Error>> {
+            Ok(Regex::new(pattern)?)
+        }
+        assert!(compile("a+").is_ok());
+        let err = compile("ab(c").unwrap_err();
+        assert_eq!(err.to_string(), "unbalanced parenthesis at offset 2");
+        assert_eq!(RegexError::UnknownFlag('x').to_string(), "unknown flag `x`");
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use crate::nfa::{Context, NFAGraph, StateId, Transition};
+
+pub const DEFAULT_CACHE_CAPACITY: usize = 4096;
+
+// A DFA built on demand while matching: every state is a set of graph
+// states, and each `(state, char)` transition is computed with the usual
+// move + closure the first time it's taken and looked up afterwards. The
+// cache is bounded; once it's full, matching carries on by plain NFA
+// simulation instead of caching more.
+#[derive(Debug)]
+pub struct LazyDfa<'g> {
+    graph: &'g NFAGraph,
+    capacity: usize,
+    sets: Vec<Vec<StateId>>,
+    ids: HashMap<Vec<StateId>, usize>,
+    transitions: HashMap<(usize, char), usize>,
+    // assertions depend on the next char, which a cached transition can't
+    // know, normalization has to see the whole input first and the sets
+    // have no room for the counters of counted loops, so those graphs are
+    // always simulated directly
+    simulate: bool,
+}
+
+impl<'g> LazyDfa<'g> {
+    pub fn new(graph: &'g NFAGraph) -> Self {
+        Self::with_capacity(graph, DEFAULT_CACHE_CAPACITY)
+    }
+
+    // `capacity` bounds the number of cached transitions.
+    pub fn with_capacity(graph: &'g NFAGraph, capacity: usize) -> Self {
+        let simulate = graph.normalized()
+            || graph.counters > 0
+            || graph.states.values().any(|state| {
+                state
+                    .outs
+                    .values()
+                    .any(|t| matches!(t, Transition::Assert(_)))
+            });
+        let mut dfa = Self {
+            graph,
+            capacity,
+            sets: Vec::new(),
+            ids: HashMap::new(),
+            transitions: HashMap::new(),
+            simulate,
+        };
+        let start = dfa.graph.closure(vec![graph.start], Context::default());
+        dfa.intern(start);
+        dfa
+    }
+
+    // Number of cached transitions, never more than the capacity.
+    pub fn cache_len(&self) -> usize {
+        self.transitions.len()
+    }
+
+    // Full match, like `NFAGraph::is_match`.
+    pub fn is_match(&mut self, s: &str) -> bool {
+        if !self.graph.valid {
+            return false;
+        }
+        if self.simulate {
+            return self.graph.is_match(s);
+        }
+        let mut current = 0;
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if let Some(next) = self.transitions.get(&(current, c)) {
+                current = *next;
+            } else {
+                let set = self.step(&self.sets[current], c);
+                if self.transitions.len() >= self.capacity {
+                    return self.simulate_from(set, chars);
+                }
+                let next = self.intern(set);
+                self.transitions.insert((current, c), next);
+                current = next;
+            }
+            if self.sets[current].is_empty() {
+                return false;
+            }
+        }
+        self.accepts(&self.sets[current])
+    }
+
+    fn step(&self, set: &[StateId], c: char) -> Vec<StateId> {
+        self.graph
+            .closure(self.graph.move2(c, set), Context::default())
+    }
+
+    fn simulate_from(&self, mut set: Vec<StateId>, chars: std::str::Chars) -> bool {
+        for c in chars {
+            if set.is_empty() {
+                return false;
+            }
+            set = self.step(&set, c);
+        }
+        self.accepts(&set)
+    }
+
+    fn accepts(&self, set: &[StateId]) -> bool {
+        set.iter().any(|id| self.graph.is_accepting(id))
+    }
+
+    fn intern(&mut self, mut set: Vec<StateId>) -> usize {
+        // sets are keyed by content, so give them a canonical order
+        set.sort();
+        if let Some(id) = self.ids.get(&set) {
+            return *id;
+        }
+        self.sets.push(set.clone());
+        self.ids.insert(set, self.sets.len() - 1);
+        self.sets.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LazyDfa;
+    use crate::nfa::NFAGraph;
+
+    #[test]
+    fn test_is_match() {
+        let inputs = [
+            "", "abb", "aabb", "ab", "abcbd", "ad", "a\nb", "abba", "xyz",
+        ];
+        for pattern in ["(a|b)*abb", "a(b|c)*d", "a.b", "[^x]+", "\\p{L}+", "\\bab"] {
+            let graph = NFAGraph::new(pattern);
+            let mut dfa = LazyDfa::new(&graph);
+            for input in inputs {
+                assert_eq!(
+                    dfa.is_match(input),
+                    graph.is_match(input),
+                    "{pattern} {input}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cache_bounded() {
+        let graph = NFAGraph::new("(a|b)*abb");
+        let input = format!("{}abb", "ab".repeat(10_000));
+        let mut dfa = LazyDfa::new(&graph);
+        assert!(dfa.is_match(&input));
+        // a handful of subsets cover the whole input
+        assert!(dfa.cache_len() <= 10);
+
+        let mut dfa = LazyDfa::with_capacity(&graph, 2);
+        assert!(dfa.is_match(&input));
+        assert!(!dfa.is_match(&input[1..input.len() - 1]));
+        assert_eq!(dfa.cache_len(), 2);
+    }
+}
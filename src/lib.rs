@@ -1,7 +1,65 @@
-mod nfa;
+pub mod builder;
+pub mod captures;
+pub mod dfa;
+pub mod error;
+pub mod lazy_dfa;
+pub mod matcher;
+pub mod nfa;
+pub mod pike;
+pub mod regex;
+pub mod runner;
+pub mod set;
+pub mod source;
+
+use builder::RegexBuilder;
+use error::RegexError;
+use nfa::{check_arity, parse_pattern, postfix_tokens};
+
+// Parses, compiles and matches in one go. Every failure is reported as a
+// `RegexError`, so this is safe to drive from a fuzzer.
+pub fn parse_and_match(pattern: &str, input: &str) -> Result<bool, RegexError> {
+    let graph = RegexBuilder::new(pattern).build()?;
+    Ok(graph.is_match(input))
+}
+
+// Checks the syntax of `pattern` the way `RegexBuilder` with its defaults
+// would, without compiling it, e.g. to validate a pattern as it's typed.
+// Limits that only the compiled graph runs into, like the size limit, are
+// not checked.
+pub fn validate(pattern: &str) -> Result<(), RegexError> {
+    let (post, _) = parse_pattern(pattern, true, true)?;
+    check_arity(&postfix_tokens(&post)?)
+}
+
+// xorshift, so randomized tests are random-looking but reproducible
+#[cfg(test)]
+pub(crate) struct XorShift(u64);
+
+#[cfg(test)]
+impl XorShift {
+    // `seed` must not be zero.
+    pub(crate) fn new(seed: u64) -> Self {
+        XorShift(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Fewer than `max_len` items drawn at random from `items`.
+    pub(crate) fn draw<T: Copy>(&mut self, items: &[T], max_len: u64) -> Vec<T> {
+        (0..self.next_u64() % max_len)
+            .map(|_| items[(self.next_u64() % items.len() as u64) as usize])
+            .collect()
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use crate::error::RegexError;
     use crate::nfa;
 
     #[test]
@@ -9,11 +67,65 @@ mod tests {
         {
             let pattern = "a+b+";
             let graph = nfa::NFAGraph::new(pattern);
-            assert_eq!(graph.is_match("aaaabbb"), true);
+            assert!(graph.is_match("aaaabbb"));
             let pattern = "a(b|c)*";
             let graph = nfa::NFAGraph::new(pattern);
-            assert_eq!(graph.is_match("abbcbbcc"), true);
-            assert_eq!(graph.is_match("bcbbcc"), false);
+            assert!(graph.is_match("abbcbbcc"));
+            assert!(!graph.is_match("bcbbcc"));
+        }
+    }
+
+    #[test]
+    pub fn test_validate() {
+        for pattern in [
+            "",
+            "a+b+",
+            "(a|b)*c",
+            "(?P<x>\\d+)-\\w",
+            "[^a-z]|\\.",
+            "a|(b|)",
+        ] {
+            assert_eq!(crate::validate(pattern), Ok(()), "{pattern}");
+        }
+        assert_eq!(crate::validate("a(b"), Err(RegexError::UnbalancedParen(1)));
+        assert_eq!(crate::validate("a)"), Err(RegexError::UnbalancedParen(1)));
+        assert_eq!(
+            crate::validate("+a"),
+            Err(RegexError::DanglingQuantifier(0))
+        );
+        assert_eq!(crate::validate("(?x:a)"), Err(RegexError::UnknownFlag('x')));
+        assert_eq!(crate::validate("[a"), Err(RegexError::IllegalPattern));
+        assert_eq!(crate::validate("a|"), Err(RegexError::IllegalPattern));
+    }
+
+    #[test]
+    pub fn test_parse_and_match() {
+        assert_eq!(crate::parse_and_match("a+b+", "aabb"), Ok(true));
+        assert_eq!(crate::parse_and_match("a+b+", "ba"), Ok(false));
+        assert_eq!(
+            crate::parse_and_match("a|", "a"),
+            Err(RegexError::IllegalPattern)
+        );
+        assert_eq!(crate::parse_and_match("a-b", "a-b"), Ok(true));
+        assert_eq!(
+            crate::parse_and_match("a{b", "a{b"),
+            Err(RegexError::IllegalPattern)
+        );
+
+        let mut rng = crate::XorShift::new(0x2545_f491_4f6c_dd1d);
+        let alphabet: Vec<char> = "ab()|*+?.\\[]{}^$-éあ1 ".chars().collect();
+        for _ in 0..2000 {
+            let pattern: String = rng.draw(&alphabet, 12).into_iter().collect();
+            let input: String = rng.draw(&alphabet, 8).into_iter().collect();
+            let _ = crate::parse_and_match(&pattern, &input);
+            // validating agrees with building on every pattern in the corpus
+            assert_eq!(
+                crate::validate(&pattern),
+                crate::builder::RegexBuilder::new(&pattern)
+                    .build()
+                    .map(|_| ()),
+                "{pattern}"
+            );
         }
     }
 }
@@ -0,0 +1,156 @@
+use crate::nfa::{Context, NFAGraph, StateId, Transition};
+
+// Runs `is_match` for one graph over many inputs without allocating per
+// call: the active state sets and the bookkeeping for them are kept between
+// calls and only cleared.
+#[derive(Debug, Clone)]
+pub struct Matcher<'g> {
+    graph: &'g NFAGraph,
+    current: Vec<StateId>,
+    next: Vec<StateId>,
+    stack: Vec<StateId>,
+    // indexed by state id, set for the members of the set being built
+    seen: Vec<bool>,
+}
+
+impl<'g> Matcher<'g> {
+    pub fn new(graph: &'g NFAGraph) -> Self {
+        let len = graph.states.keys().map(|id| id.0 + 1).max().unwrap_or(0);
+        Self {
+            graph,
+            current: Vec::new(),
+            next: Vec::new(),
+            stack: Vec::new(),
+            seen: vec![false; len],
+        }
+    }
+
+    // Same result as `NFAGraph::is_match`.
+    pub fn is_match(&mut self, s: &str) -> bool {
+        #[cfg(feature = "unicode-normalization")]
+        if self.graph.normalize {
+            use unicode_normalization::UnicodeNormalization;
+            let normalized: String = s.nfc().collect();
+            return self.run(&normalized);
+        }
+        self.run(s)
+    }
+
+    fn run(&mut self, s: &str) -> bool {
+        let graph = self.graph;
+        if !graph.valid {
+            return false;
+        }
+        if graph.literal {
+            return s == graph.prefix;
+        }
+        // the reused sets have no room for the counters of counted loops
+        if graph.counters > 0 {
+            return graph.is_match(s);
+        }
+        self.current.clear();
+        self.current.push(graph.start);
+        self.close(Context::at(s, 0));
+        for (i, c) in s.char_indices() {
+            if self.current.len() > graph.active_state_limit {
+                return false;
+            }
+            self.step(c);
+            if self.current.is_empty() {
+                return false;
+            }
+            self.close(Context::at(s, i + c.len_utf8()));
+        }
+        self.current.len() <= graph.active_state_limit
+            && self.current.iter().any(|id| graph.is_accepting(id))
+    }
+
+    // Extends `current` to its closure under the epsilon edges and the
+    // assertions that hold in `ctx`.
+    fn close(&mut self, ctx: Context) {
+        for id in self.current.iter() {
+            self.seen[id.0] = true;
+        }
+        self.stack.extend_from_slice(&self.current);
+        while let Some(id) = self.stack.pop() {
+            for (target, transition) in self.graph.states[&id].outs.iter() {
+                let follow = match transition {
+                    Transition::Assert(assertion) => assertion.holds(ctx),
+                    t => t.is_epsilon(),
+                };
+                if follow && !self.seen[target.0] {
+                    self.seen[target.0] = true;
+                    self.current.push(*target);
+                    self.stack.push(*target);
+                }
+            }
+        }
+        for id in self.current.iter() {
+            self.seen[id.0] = false;
+        }
+    }
+
+    // Replaces `current` by the targets of its edges consuming `c`.
+    fn step(&mut self, c: char) {
+        self.next.clear();
+        for id in self.current.iter() {
+            let by_char = self.graph.char_index.get(id);
+            if let Some(targets) = by_char.and_then(|by_char| by_char.get(&c)) {
+                for target in targets {
+                    if !self.seen[target.0] {
+                        self.seen[target.0] = true;
+                        self.next.push(*target);
+                    }
+                }
+            }
+            for (target, transition) in self.graph.states[id].outs.iter() {
+                // indexed `Char` edges were handled above
+                if by_char.is_some() && matches!(transition, Transition::Char(_)) {
+                    continue;
+                }
+                if self.graph.takes(transition, c) && !self.seen[target.0] {
+                    self.seen[target.0] = true;
+                    self.next.push(*target);
+                }
+            }
+        }
+        for id in self.next.iter() {
+            self.seen[id.0] = false;
+        }
+        std::mem::swap(&mut self.current, &mut self.next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matcher;
+    use crate::nfa::NFAGraph;
+
+    #[test]
+    fn test_is_match() {
+        let mut rng = crate::XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let alphabet: Vec<char> = "abc d\né".chars().collect();
+        for pattern in [
+            "(a|b)*abb",
+            "a(b|c)*d",
+            "a.b",
+            "[^x]+",
+            "\\p{L}+",
+            "\\bab\\b",
+            "^a|c$",
+            "abc",
+            "",
+        ] {
+            let graph = NFAGraph::new(pattern);
+            let mut matcher = Matcher::new(&graph);
+            for _ in 0..500 {
+                let input: String = rng.draw(&alphabet, 8).into_iter().collect();
+                assert_eq!(
+                    matcher.is_match(&input),
+                    graph.is_match(&input),
+                    "{pattern} {input:?}"
+                );
+            }
+        }
+    }
+}
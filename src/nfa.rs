@@ -1,15 +1,463 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::BufRead;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct StateId(usize);
+use crate::builder::{RegexBuilder, DEFAULT_SIZE_LIMIT};
+use crate::error::{MatchError, RegexError};
+use crate::source::TextSource;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateId(pub(crate) usize);
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Transition {
     Epsilon,
+    // An epsilon edge of a lazy quantifier. Plain matching follows it like
+    // `Epsilon`, but `find` prefers paths that take fewer of them.
+    LazyEpsilon,
     Char(Vec<char>),
+    // any char except the listed ones, from a negated class like `[^abc]`
+    NotChar(Vec<char>),
+    // Unicode classes like `\p{L}` are far too large to list, so they are
+    // tested with a predicate instead
+    Class(ClassId),
+    // any char in none of the classes and not among the chars, from a
+    // negated class like `\P{N}` or `[^x\d\s]`
+    NotClass(Vec<ClassId>, Vec<char>),
+    Byte(Vec<u8>),
+    Any,
+    // zero-width edge that can only be followed where the assertion holds
+    Assert(Assertion),
+    // zero-width edge of a counted loop, followed depending on the counter
+    // of the thread taking it
+    Counter(CounterOp),
+    // an epsilon edge recording the current offset in a capture slot: slot
+    // 2n where group n starts and 2n + 1 where it ends
+    Save(usize),
 }
 
-#[derive(Debug, Clone)]
+// The Unicode classes for `\p{...}`, backed by the `char` predicates of the
+// standard library. Letters use the Alphabetic property, which is slightly
+// larger than the L category. Separators are the few chars of Zs, Zl and
+// Zp listed out, since White_Space also has tabs and line breaks.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ClassId {
+    Letter,
+    Uppercase,
+    Lowercase,
+    Number,
+    Separator,
+    Whitespace,
+    Control,
+}
+
+impl ClassId {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let id = match name {
+            "L" | "Letter" => ClassId::Letter,
+            "Lu" | "Uppercase_Letter" => ClassId::Uppercase,
+            "Ll" | "Lowercase_Letter" => ClassId::Lowercase,
+            "N" | "Number" => ClassId::Number,
+            "Z" | "Separator" => ClassId::Separator,
+            "White_Space" => ClassId::Whitespace,
+            "Cc" | "Control" => ClassId::Control,
+            _ => return None,
+        };
+        Some(id)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClassId::Letter => "L",
+            ClassId::Uppercase => "Lu",
+            ClassId::Lowercase => "Ll",
+            ClassId::Number => "N",
+            ClassId::Separator => "Z",
+            ClassId::Whitespace => "White_Space",
+            ClassId::Control => "Cc",
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            ClassId::Letter => c.is_alphabetic(),
+            ClassId::Uppercase => c.is_uppercase(),
+            ClassId::Lowercase => c.is_lowercase(),
+            ClassId::Number => c.is_numeric(),
+            ClassId::Separator => matches!(
+                c,
+                ' ' | '\u{a0}' | '\u{1680}' | '\u{2000}'
+                    ..='\u{200a}' | '\u{2028}' | '\u{2029}' | '\u{202f}' | '\u{205f}' | '\u{3000}'
+            ),
+            ClassId::Whitespace => c.is_whitespace(),
+            ClassId::Control => c.is_control(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Assertion {
+    WordBoundary,
+    NotWordBoundary,
+    // `^` and `$`
+    StartText,
+    EndText,
+    // `^` and `$` in multiline mode, which also hold next to a line
+    // terminator
+    StartLine(LineTerminator),
+    EndLine(LineTerminator),
+    // the next (previous) char, if any, isn't one the char edge takes. Ends
+    // an atomic group, so it can't give back chars it could have taken.
+    NotBefore(Box<Transition>),
+    NotAfter(Box<Transition>),
+}
+
+// What ends a line for multiline anchors and the `.` wildcard.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LineTerminator {
+    #[default]
+    Lf,
+    Cr,
+    // `\r\n` as one terminator, and a lone `\r` or `\n` as well
+    Crlf,
+    // `\n\r`, which is what `Crlf` turns into in reversed input
+    LfCr,
+}
+
+impl LineTerminator {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            LineTerminator::Lf => c == '\n',
+            LineTerminator::Cr => c == '\r',
+            LineTerminator::Crlf | LineTerminator::LfCr => c == '\n' || c == '\r',
+        }
+    }
+
+    // Whether a line starts at `ctx`. A two-char terminator is never split.
+    fn starts_line(&self, ctx: Context) -> bool {
+        match (self, ctx.prev) {
+            (_, None) => true,
+            (LineTerminator::Crlf, Some('\r')) => ctx.next != Some('\n'),
+            (LineTerminator::LfCr, Some('\n')) => ctx.next != Some('\r'),
+            (t, Some(c)) => t.contains(c),
+        }
+    }
+
+    // Whether a line ends at `ctx`.
+    fn ends_line(&self, ctx: Context) -> bool {
+        match (self, ctx.next) {
+            (_, None) => true,
+            (LineTerminator::Crlf, Some('\n')) => ctx.prev != Some('\r'),
+            (LineTerminator::LfCr, Some('\r')) => ctx.prev != Some('\n'),
+            (t, Some(c)) => t.contains(c),
+        }
+    }
+
+    fn reversed(&self) -> Self {
+        match self {
+            LineTerminator::Crlf => LineTerminator::LfCr,
+            LineTerminator::LfCr => LineTerminator::Crlf,
+            t => *t,
+        }
+    }
+}
+
+impl Assertion {
+    // The assertion that holds in the reversed input wherever this one holds
+    // in the original.
+    pub fn reversed(&self) -> Self {
+        match self {
+            Assertion::StartText => Assertion::EndText,
+            Assertion::EndText => Assertion::StartText,
+            Assertion::StartLine(t) => Assertion::EndLine(t.reversed()),
+            Assertion::EndLine(t) => Assertion::StartLine(t.reversed()),
+            Assertion::NotBefore(t) => Assertion::NotAfter(t.clone()),
+            Assertion::NotAfter(t) => Assertion::NotBefore(t.clone()),
+            a => a.clone(),
+        }
+    }
+
+    pub fn holds(&self, ctx: Context) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_');
+        match self {
+            Assertion::WordBoundary => is_word(ctx.prev) != is_word(ctx.next),
+            Assertion::NotWordBoundary => is_word(ctx.prev) == is_word(ctx.next),
+            Assertion::StartText => ctx.prev.is_none(),
+            Assertion::EndText => ctx.next.is_none(),
+            Assertion::StartLine(t) => t.starts_line(ctx),
+            Assertion::EndLine(t) => t.ends_line(ctx),
+            Assertion::NotBefore(t) => !ctx.next.is_some_and(|c| t.takes_char(c)),
+            Assertion::NotAfter(t) => !ctx.prev.is_some_and(|c| t.takes_char(c)),
+        }
+    }
+}
+
+// A bracket class like `[a-z_]` or `[^[:digit:]]` on its own, parsed the way
+// `RegexBuilder` parses it with its defaults, e.g. to classify the chars
+// of a tokenizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    // sorted and without duplicates
+    members: Vec<char>,
+    classes: Vec<ClassId>,
+    negated: bool,
+}
+
+impl CharClass {
+    pub fn parse(s: &str) -> Result<Self, RegexError> {
+        let mut chars = s.chars().peekable();
+        if chars.next() != Some('[') {
+            return Err(RegexError::IllegalPattern);
+        }
+        match parse_class(&mut chars, true, false) {
+            Some(class) if chars.next().is_none() => Ok(class),
+            _ => Err(RegexError::IllegalPattern),
+        }
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let listed =
+            self.members.binary_search(&c).is_ok() || self.classes.iter().any(|id| id.contains(c));
+        listed != self.negated
+    }
+
+    // The postfix atom `[...]` (or `[^...]` when negated) listing every
+    // member, with `\`, `]` and `^` escaped among them. Unicode classes are
+    // alternated with the listed members, or listed as `\p{...}` among them
+    // when negated.
+    fn postfix(&self) -> String {
+        let escaped = |atom: &mut String, c: char| {
+            if matches!(c, '\\' | ']' | '^') {
+                atom.push('\\');
+            }
+            atom.push(c);
+        };
+        if self.negated && !self.classes.is_empty() {
+            let mut atom = String::from("[^");
+            for c in self.members.iter() {
+                escaped(&mut atom, *c);
+            }
+            for id in self.classes.iter() {
+                atom.push_str(&format!("\\p{{{}}}", id.name()));
+            }
+            atom.push(']');
+            return atom;
+        }
+        let mut atom = String::new();
+        if !self.members.is_empty() || self.classes.is_empty() {
+            atom.push_str(if self.negated { "[^" } else { "[" });
+            for c in self.members.iter() {
+                escaped(&mut atom, *c);
+            }
+            atom.push(']');
+        }
+        let alternated = !atom.is_empty();
+        for (i, id) in self.classes.iter().enumerate() {
+            atom.push_str(&format!("\\p{{{}}}", id.name()));
+            if alternated || i > 0 {
+                atom.push('|');
+            }
+        }
+        atom
+    }
+}
+
+// The characters around the current input position, which is all the
+// assertions need to know.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Context {
+    pub prev: Option<char>,
+    pub next: Option<char>,
+}
+
+impl Context {
+    pub fn at(s: &str, at: usize) -> Self {
+        Self {
+            prev: s[..at].chars().next_back(),
+            next: s[at..].chars().next(),
+        }
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (s, t) = match self {
+            Assertion::WordBoundary => ("\\b", None),
+            Assertion::NotWordBoundary => ("\\B", None),
+            Assertion::StartText => ("^", None),
+            Assertion::EndText => ("$", None),
+            Assertion::StartLine(t) => ("(?m:^)", Some(t)),
+            Assertion::EndLine(t) => ("(?m:$)", Some(t)),
+            Assertion::NotBefore(t) => return write!(f, "(?!{})", t),
+            Assertion::NotAfter(t) => return write!(f, "(?<!{})", t),
+        };
+        f.write_str(s)?;
+        // other terminators than the default `\n` are spelled out
+        match t {
+            Some(LineTerminator::Cr) => f.write_str("/\\r"),
+            Some(LineTerminator::Crlf) => f.write_str("/\\r\\n"),
+            Some(LineTerminator::LfCr) => f.write_str("/\\n\\r"),
+            _ => Ok(()),
+        }
+    }
+}
+
+// What a `Counter` edge of a counted loop like `a{2,5}` does. Each thread
+// has a counter per loop, the iterations of the body it has completed.
+// Entering the loop resets it, and at the end of the body the thread goes
+// round again below `max` and leaves from `min` on. Without a `max`, the
+// counter stops at `min`, so an unbounded loop doesn't keep threads apart.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum CounterOp {
+    Reset(usize),
+    Again {
+        counter: usize,
+        min: usize,
+        max: Option<usize>,
+    },
+    Done {
+        counter: usize,
+        min: usize,
+    },
+}
+
+impl CounterOp {
+    fn shifted(&self, by: usize) -> Self {
+        match *self {
+            CounterOp::Reset(counter) => CounterOp::Reset(counter + by),
+            CounterOp::Again { counter, min, max } => CounterOp::Again {
+                counter: counter + by,
+                min,
+                max,
+            },
+            CounterOp::Done { counter, min } => CounterOp::Done {
+                counter: counter + by,
+                min,
+            },
+        }
+    }
+
+    // The counters after following the edge, `None` if it can't be.
+    pub(crate) fn apply(&self, counters: &[usize]) -> Option<Vec<usize>> {
+        let mut counters = counters.to_vec();
+        match *self {
+            CounterOp::Reset(counter) => counters[counter] = 0,
+            CounterOp::Again { counter, min, max } => {
+                let done = counters[counter] + 1;
+                match max {
+                    Some(max) if done >= max => return None,
+                    Some(_) => counters[counter] = done,
+                    None => counters[counter] = done.min(min),
+                }
+            }
+            CounterOp::Done { counter, min } => {
+                if counters[counter] + 1 < min {
+                    return None;
+                }
+            }
+        }
+        Some(counters)
+    }
+}
+
+impl fmt::Display for CounterOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CounterOp::Reset(counter) => write!(f, "c{} = 0", counter),
+            CounterOp::Again {
+                counter,
+                max: Some(max),
+                ..
+            } => write!(f, "c{} + 1 < {}", counter, max),
+            CounterOp::Again { counter, .. } => write!(f, "c{} + 1", counter),
+            CounterOp::Done { counter, min } => write!(f, "c{} + 1 >= {}", counter, min),
+        }
+    }
+}
+
+// Compact labels for logs and the DOT export: `ε` for epsilon edges, the
+// char itself for a single char and `[abc]` for sets.
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transition::Epsilon => write!(f, "ε"),
+            Transition::LazyEpsilon => write!(f, "ε?"),
+            Transition::Char(chars) if chars.len() == 1 => write!(f, "{}", chars[0]),
+            Transition::Char(chars) => write!(f, "[{}]", chars.iter().collect::<String>()),
+            Transition::NotChar(chars) => write!(f, "[^{}]", chars.iter().collect::<String>()),
+            Transition::Class(id) => write!(f, "\\p{{{}}}", id.name()),
+            Transition::NotClass(ids, chars) if ids.len() == 1 && chars.is_empty() => {
+                write!(f, "\\P{{{}}}", ids[0].name())
+            }
+            Transition::NotClass(ids, chars) => {
+                write!(f, "[^{}", chars.iter().collect::<String>())?;
+                for id in ids {
+                    write!(f, "\\p{{{}}}", id.name())?;
+                }
+                write!(f, "]")
+            }
+            Transition::Byte(bytes) if bytes.len() == 1 => write!(f, "\\x{:02x}", bytes[0]),
+            Transition::Byte(bytes) => {
+                write!(f, "[")?;
+                for b in bytes {
+                    write!(f, "\\x{:02x}", b)?;
+                }
+                write!(f, "]")
+            }
+            Transition::Any => write!(f, "."),
+            Transition::Save(slot) => write!(f, "save {}", slot),
+            Transition::Assert(assertion) => write!(f, "{}", assertion),
+            Transition::Counter(op) => write!(f, "{}", op),
+        }
+    }
+}
+
+impl Transition {
+    fn enter(lazy: bool) -> Self {
+        if lazy {
+            Transition::LazyEpsilon
+        } else {
+            Transition::Epsilon
+        }
+    }
+
+    // Whether this edge consumes `c`, for the edges that don't depend on
+    // the options of a graph.
+    pub fn takes_char(&self, c: char) -> bool {
+        match self {
+            Transition::Char(chars) => chars.contains(&c),
+            Transition::NotChar(chars) => !chars.contains(&c),
+            Transition::Class(id) => id.contains(c),
+            Transition::NotClass(ids, chars) => {
+                !chars.contains(&c) && !ids.iter().any(|id| id.contains(c))
+            }
+            _ => false,
+        }
+    }
+
+    // Whether this is an edge `takes_char` knows about.
+    pub(crate) fn takes_char_only(&self) -> bool {
+        matches!(
+            self,
+            Transition::Char(_)
+                | Transition::NotChar(_)
+                | Transition::Class(_)
+                | Transition::NotClass(..)
+        )
+    }
+
+    pub fn is_epsilon(&self) -> bool {
+        matches!(
+            self,
+            Transition::Epsilon | Transition::LazyEpsilon | Transition::Save(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct State {
     pub id: StateId,
     pub outs: HashMap<StateId, Transition>,
@@ -26,72 +474,717 @@ impl State {
 
 #[derive(Debug)]
 pub struct Frag {
-    start: StateId,
-    end: Vec<StateId>,
+    pub start: StateId,
+    pub end: Vec<StateId>,
 }
 
-#[derive(Debug)]
+impl Frag {
+    fn dangling() -> Self {
+        Self {
+            start: StateId(0),
+            end: vec![StateId(0)],
+        }
+    }
+}
+
+// One step of the postfix form. The compiler works on these, so an
+// operator can never be confused with the char it is spelled with:
+// `Literal('.')` matches a dot while `Concat` joins two fragments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PostfixToken {
+    Concat,
+    Alternate,
+    // the quantifiers, lazy when set
+    Optional(bool),
+    Star(bool),
+    Plus(bool),
+    // closes capture group n around the fragment before it
+    Group(usize),
+    Literal(char),
+    // the members of a `[...]` set and whether it's negated
+    Set(Vec<char>, bool),
+    // `\p{...}`, or `\P{...}` when negated
+    Class(ClassId, bool),
+    // an atom that is a single edge, like `_` or `\b`
+    Edge(Transition),
+    // `^` and `$`, at line boundaries too in multiline mode
+    Start,
+    End,
+    // an alternation of literal words, compiled into a trie
+    Words(Vec<String>),
+    // `!`, turns the single char edge before it into a `NotBefore` assertion
+    NotBefore,
+    // `{min,max}` or `{min,}`, a counted repetition
+    Repeat(usize, Option<usize>),
+}
+
+impl PostfixToken {
+    // How many fragments the token takes off the stack.
+    fn operands(&self) -> usize {
+        match self {
+            PostfixToken::Concat | PostfixToken::Alternate => 2,
+            PostfixToken::Optional(_)
+            | PostfixToken::Star(_)
+            | PostfixToken::Plus(_)
+            | PostfixToken::Group(_)
+            | PostfixToken::NotBefore
+            | PostfixToken::Repeat(..) => 1,
+            _ => 0,
+        }
+    }
+}
+
+// Counts reported by `NFAGraph::stats`. Epsilon edges include the lazy and
+// capture ones, char edges are all the edges that consume input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GraphStats {
+    pub state_count: usize,
+    pub transition_count: usize,
+    pub epsilon_count: usize,
+    pub char_edge_count: usize,
+}
+
+// What `NFAGraph::explain` found out about a full match of some input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchExplanation {
+    pub matched: bool,
+    // Byte offset of the first char no active state takes, or the input
+    // length when the input ran out before the pattern was done. `None`
+    // when the input matched.
+    pub position: Option<usize>,
+    // The chars the char edges of the last active states would have taken
+    // at `position`. Classes and wildcards aren't listed; `open` tells
+    // whether there are any.
+    pub expected: BTreeSet<char>,
+    pub open: bool,
+}
+
+// Byte offsets of a match within the searched string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Match {
+    // The matched slice of the string that was searched.
+    pub fn as_str<'t>(&self, input: &'t str) -> &'t str {
+        &input[self.start..self.end]
+    }
+
+    // Length in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+// Iterator over the non-overlapping matches of a graph in a string, found
+// lazily one `find_at` at a time. See `NFAGraph::find_iter`.
+#[derive(Debug, Clone)]
+pub struct Matches<'r, 't> {
+    graph: &'r NFAGraph,
+    text: &'t str,
+    // where the next search starts, past the end once exhausted
+    pos: usize,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let graph = self.graph;
+        let text = self.text;
+        next_match(text, &mut self.pos, graph.allow_empty, |at| {
+            graph.find_at(text, at)
+        })
+    }
+}
+
+// One step of a non-overlapping match iterator over `text`, searching from
+// `*pos` with `find_at`. An empty match moves `*pos` a char further so the
+// iteration always makes progress, and past the end once it is done.
+pub(crate) fn next_match(
+    text: &str,
+    pos: &mut usize,
+    allow_empty: bool,
+    mut find_at: impl FnMut(usize) -> Option<Match>,
+) -> Option<Match> {
+    while *pos <= text.len() {
+        let Some(m) = find_at(*pos) else {
+            *pos = text.len() + 1;
+            return None;
+        };
+        if m.start != m.end {
+            *pos = m.end;
+            return Some(m);
+        }
+        *pos = match text[m.end..].chars().next() {
+            Some(c) => m.end + c.len_utf8(),
+            None => text.len() + 1,
+        };
+        if allow_empty {
+            return Some(m);
+        }
+    }
+    None
+}
+
+// The threads of a simulation over a graph. Without counted loops a thread
+// is only the state it is in. With them it also carries a counter per loop,
+// and threads in one state with different counters can't be merged, so
+// ambiguous patterns can keep up to the product of the loop bounds threads
+// per state.
+#[derive(Debug, Clone)]
+pub(crate) enum Threads {
+    States(Vec<StateId>),
+    Counted(Vec<(StateId, Vec<usize>)>),
+}
+
+impl Threads {
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Threads::States(ids) => ids.len(),
+            Threads::Counted(threads) => threads.len(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The states the threads are in, each listed once.
+    pub(crate) fn states(&self) -> Vec<StateId> {
+        match self {
+            Threads::States(ids) => ids.clone(),
+            Threads::Counted(threads) => {
+                let mut seen = HashSet::new();
+                threads
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .filter(|id| seen.insert(*id))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CompileConfig {
+    pub size_limit: usize,
+    // compile literals to `Transition::Byte` edges over their UTF-8 encoding
+    pub bytes: bool,
+    // let `^` and `$` match at line boundaries too
+    pub multiline: bool,
+    pub line_terminator: LineTerminator,
+    // compile alternations of plain literals into a trie, see `factor_words`
+    pub trie: bool,
+    // compile `{min,max}` to a counted loop instead of copies of its operand
+    pub counted_loops: bool,
+}
+
+impl Default for CompileConfig {
+    fn default() -> Self {
+        Self {
+            size_limit: DEFAULT_SIZE_LIMIT,
+            bytes: false,
+            multiline: false,
+            line_terminator: LineTerminator::Lf,
+            trie: false,
+            counted_loops: false,
+        }
+    }
+}
+
+// A compiled graph is plain data and every matching method takes `&self`,
+// so it is `Send + Sync` and can be shared through an `Arc`. Anything
+// computed while matching lives on the stack, caches such as `char_index`
+// are built up front and only read afterwards, and the mutable ones, like
+// `LazyDfa`, are kept outside the graph. Keep it that way.
+#[derive(Debug, Clone)]
 pub struct NFAGraph {
     pub states: HashMap<StateId, State>,
     pub last_id: usize,
     pub start: StateId,
     pub ends: Vec<StateId>,
+    // cleared when compilation hit malformed postfix and the graph is only
+    // partially built
+    pub valid: bool,
+    // upper bound on the size of the active state set while matching
+    pub active_state_limit: usize,
+    // whether the `.` wildcard also matches the line terminator
+    pub dot_matches_newline: bool,
+    pub line_terminator: LineTerminator,
+    // whether `find_iter` reports empty matches
+    pub allow_empty: bool,
+    // whether `find` only tries the offset the search starts at
+    pub anchored: bool,
+    // whether `is_match` and friends bring the input into NFC first
+    #[cfg(feature = "unicode-normalization")]
+    pub normalize: bool,
+    // start states of the top-level alternatives in pattern order, empty when
+    // the pattern has no top-level `|`
+    pub alternatives: Vec<StateId>,
+    // literal every match has to start with, used by `find` to skip ahead
+    pub prefix: String,
+    // the pattern is exactly `prefix`, so matching is a plain string search
+    pub(crate) literal: bool,
+    // one entry per capture group, in the order of their opening parens
+    pub group_names: Vec<Option<String>>,
+    // number of counted loops, each with a counter per thread
+    pub(crate) counters: usize,
+    // targets of the `Char` edges of each state by input char, so `move2`
+    // doesn't scan the member lists. Built by `index_chars`; states missing
+    // from it are scanned as before.
+    pub(crate) char_index: HashMap<StateId, HashMap<char, Vec<StateId>>>,
+    // `reverse` of this graph, which `search` runs back from the end of a
+    // match to bound its start. Built by `index_chars` along with the char
+    // index; `None` for counted graphs, which can't be run reversed.
+    pub(crate) reversed: Option<Box<NFAGraph>>,
+}
+
+// Structural equality: the same states with the same edges. `HashMap`
+// equality already ignores ordering, `ends` is compared as a set, and the
+// id allocation counter, the char index and the reversed graph aren't part
+// of the structure.
+impl PartialEq for NFAGraph {
+    fn eq(&self, other: &Self) -> bool {
+        let ends = |graph: &NFAGraph| graph.ends.iter().copied().collect::<HashSet<StateId>>();
+        self.states == other.states
+            && self.start == other.start
+            && ends(self) == ends(other)
+            && self.valid == other.valid
+            && self.active_state_limit == other.active_state_limit
+            && self.dot_matches_newline == other.dot_matches_newline
+            && self.line_terminator == other.line_terminator
+            && self.allow_empty == other.allow_empty
+            && self.anchored == other.anchored
+            && self.normalized() == other.normalized()
+            && self.alternatives == other.alternatives
+            && self.prefix == other.prefix
+            && self.group_names == other.group_names
+    }
+}
+
+impl Eq for NFAGraph {}
+
+impl FromStr for NFAGraph {
+    type Err = RegexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_new(s)
+    }
 }
 
 impl NFAGraph {
+    // Panics on an illegal pattern, `try_new` reports it instead.
     pub fn new(pattern: &str) -> Self {
-        let post = re2post(pattern);
-        match post {
-            Some(post) => {
-                Self::compile(&post)
+        match parse_pattern(pattern, true, true) {
+            Ok((post, groups)) => {
+                let mut graph = Self::compile(&post);
+                graph.group_names = groups;
+                graph
             }
-            None => panic!("illegal pattern")
+            Err(_) => panic!("illegal pattern"),
         }
     }
-    pub fn compile(post: &str) -> Self {
-        let mut stack: Vec<Frag> = Vec::new();
-        let mut graph = NFAGraph {
+
+    // Fallible `new` with the builder's defaults.
+    pub fn try_new(pattern: &str) -> Result<Self, RegexError> {
+        RegexBuilder::new(pattern).build()
+    }
+
+    pub fn empty() -> Self {
+        NFAGraph {
             states: HashMap::new(),
             last_id: 0,
             start: StateId(0),
             ends: vec![StateId(0)],
+            valid: true,
+            active_state_limit: usize::MAX,
+            dot_matches_newline: false,
+            line_terminator: LineTerminator::Lf,
+            allow_empty: true,
+            anchored: false,
+            #[cfg(feature = "unicode-normalization")]
+            normalize: false,
+            alternatives: Vec::new(),
+            prefix: String::new(),
+            literal: false,
+            group_names: Vec::new(),
+            counters: 0,
+            char_index: HashMap::new(),
+            reversed: None,
+        }
+    }
+
+    pub fn compile(post: &str) -> Self {
+        let mut graph = NFAGraph::empty();
+        match Self::try_compile_subgraph(post, &mut graph, &CompileConfig::default()) {
+            Ok((frag, alternatives)) => {
+                graph.start = frag.start;
+                graph.ends = frag.end;
+                graph.alternatives = alternatives;
+                (graph.prefix, graph.literal) = graph.literal_prefix();
+                graph.index_chars();
+            }
+            Err(_) => graph.valid = false,
+        }
+        graph
+    }
+
+    // Compiles postfix written by hand, as produced by `re2post`. Unlike
+    // `compile`, malformed input is reported instead of leaving a half-built
+    // graph behind.
+    pub fn from_postfix(post: &str) -> Result<Self, RegexError> {
+        Self::compile_with(post, &CompileConfig::default())
+    }
+
+    pub(crate) fn compile_with(post: &str, config: &CompileConfig) -> Result<Self, RegexError> {
+        let mut graph = NFAGraph::empty();
+        let (frag, alternatives) = Self::try_compile_subgraph(post, &mut graph, config)?;
+        graph.start = frag.start;
+        graph.ends = frag.end;
+        graph.alternatives = alternatives;
+        (graph.prefix, graph.literal) = graph.literal_prefix();
+        graph.index_chars();
+        Ok(graph)
+    }
+
+    // Compiles `post` into the id space of `graph` and returns the resulting
+    // fragment, so several fragments can be wired together without collisions.
+    pub fn compile_subgraph(post: &str, graph: &mut NFAGraph) -> Frag {
+        // the limit is on the ids, so leave room for those already taken
+        let config = CompileConfig {
+            size_limit: graph.last_id + DEFAULT_SIZE_LIMIT,
+            ..CompileConfig::default()
+        };
+        match Self::try_compile_subgraph(post, graph, &config) {
+            Ok((frag, _)) => frag,
+            Err(_) => {
+                graph.valid = false;
+                Frag::dangling()
+            }
+        }
+    }
+
+    // The graph matching `self` followed by `other`, wired like the `.` case
+    // of the postfix compiler. The groups of `other` are numbered after
+    // those of `self`, and the matching options of `self` are kept.
+    pub fn concat(mut self, other: NFAGraph) -> NFAGraph {
+        let (start, ends, _) = self.absorb(other);
+        let first_ends = std::mem::take(&mut self.ends);
+        self.valid &= Self::patch(&mut self, &first_ends, start, Transition::Epsilon).is_ok();
+        self.ends = ends;
+        self.alternatives = Vec::new();
+        (self.prefix, self.literal) = self.literal_prefix();
+        self.index_chars();
+        self
+    }
+
+    // The graph matching either `self` or `other`, wired like the `|` case
+    // of the postfix compiler. The top-level alternatives of both are kept
+    // in order, so `find_tagged` numbers them as in the pattern `self|other`.
+    pub fn alternate(mut self, other: NFAGraph) -> NFAGraph {
+        let (start, ends, other_alternatives) = self.absorb(other);
+        let mut alternatives = if self.alternatives.is_empty() {
+            vec![self.start]
+        } else {
+            std::mem::take(&mut self.alternatives)
+        };
+        if other_alternatives.is_empty() {
+            alternatives.push(start);
+        } else {
+            alternatives.extend(other_alternatives);
+        }
+        let mut split = State::new(StateId(self.last_id));
+        let join = State::new(StateId(self.last_id + 1));
+        self.last_id += 2;
+        split.outs.insert(self.start, Transition::Epsilon);
+        split.outs.insert(start, Transition::Epsilon);
+        self.states.insert(join.id, join.clone());
+        let first_ends = std::mem::take(&mut self.ends);
+        self.valid &= Self::patch(&mut self, &first_ends, join.id, Transition::Epsilon)
+            .and_then(|()| Self::patch(&mut self, &ends, join.id, Transition::Epsilon))
+            .is_ok();
+        self.start = split.id;
+        self.states.insert(split.id, split);
+        self.ends = vec![join.id];
+        self.alternatives = alternatives;
+        (self.prefix, self.literal) = self.literal_prefix();
+        self.index_chars();
+        self
+    }
+
+    // The graph matching any of `patterns`, each built with the builder's
+    // defaults. One start state leads to the start of every pattern and
+    // their accepting states are all kept, so unlike folding `alternate`
+    // no states are added per pattern. The patterns are the top-level
+    // alternatives, so `find_tagged` reports which one matched.
+    pub fn union(patterns: &[&str]) -> Result<NFAGraph, RegexError> {
+        let mut graph = NFAGraph::empty();
+        let mut starts = Vec::with_capacity(patterns.len());
+        let mut ends = Vec::new();
+        for pattern in patterns {
+            let (start, pattern_ends, _) = graph.absorb(Self::try_new(pattern)?);
+            starts.push(start);
+            ends.extend(pattern_ends);
+        }
+        let mut split = State::new(StateId(graph.last_id));
+        graph.last_id += 1;
+        for start in starts.iter() {
+            split.outs.insert(*start, Transition::Epsilon);
+        }
+        graph.start = split.id;
+        graph.states.insert(split.id, split);
+        graph.ends = ends;
+        graph.alternatives = starts;
+        (graph.prefix, graph.literal) = graph.literal_prefix();
+        graph.index_chars();
+        Ok(graph)
+    }
+
+    // The graph matching exactly the strings both `self` and `other` match,
+    // by the product construction: a state per reachable pair of states,
+    // where zero-width edges move one side of the pair and a char moves
+    // both. Assertions stay zero-width edges, as both sides see the same
+    // chars around a position, and capture groups are dropped. Consuming
+    // edges are intersected edge by edge. That works for literal chars
+    // against anything, for negated sets and the wildcard against each other,
+    // against a Unicode class holding none of the chars they leave out and
+    // against a negated class holding all of them, and for two identical
+    // edges. It doesn't for e.g. `[^a]` against `\p{L}`, two different
+    // Unicode classes or byte edges, and the result is not valid when such a
+    // pair can be reached.
+    pub fn intersect(&self, other: &NFAGraph) -> NFAGraph {
+        let mut graph = NFAGraph::empty();
+        if !self.valid || !other.valid {
+            graph.valid = false;
+            return graph;
+        }
+        // pairs in the order they got their states, the ones from `next` on
+        // still need their edges
+        let mut pairs = vec![(self.start, other.start)];
+        let mut ids = HashMap::from([(pairs[0], StateId(0))]);
+        graph.states.insert(StateId(0), State::new(StateId(0)));
+        graph.last_id = 1;
+        graph.start = StateId(0);
+        graph.ends = Vec::new();
+        graph.counters = self.counters + other.counters;
+        let mut next = 0;
+        while let Some(&(p, q)) = pairs.get(next) {
+            let from = ids[&(p, q)];
+            next += 1;
+            if self.is_accepting(&p) && other.is_accepting(&q) {
+                graph.ends.push(from);
+            }
+            let mut edges = Vec::new();
+            for (target, t) in self.states[&p].outs.iter() {
+                if let Some(t) = zero_width(t) {
+                    edges.push(((*target, q), t));
+                }
+            }
+            for (target, t) in other.states[&q].outs.iter() {
+                let t = match zero_width(t) {
+                    // the counters of `other` come after those of `self`
+                    Some(Transition::Counter(op)) => Transition::Counter(op.shifted(self.counters)),
+                    Some(t) => t,
+                    None => continue,
+                };
+                edges.push(((p, *target), t));
+            }
+            for (p_target, p_t) in self.states[&p].outs.iter() {
+                for (q_target, q_t) in other.states[&q].outs.iter() {
+                    if zero_width(p_t).is_some() || zero_width(q_t).is_some() {
+                        continue;
+                    }
+                    match self.meet(p_t, other, q_t) {
+                        Some(Transition::Char(chars)) if chars.is_empty() => {}
+                        Some(t) => edges.push(((*p_target, *q_target), t)),
+                        None => graph.valid = false,
+                    }
+                }
+            }
+            for (pair, t) in edges {
+                let target = *ids.entry(pair).or_insert_with(|| {
+                    let id = StateId(graph.last_id);
+                    graph.last_id += 1;
+                    graph.states.insert(id, State::new(id));
+                    pairs.push(pair);
+                    id
+                });
+                // a pair reached both by a zero-width edge and by a char
+                // gets the second edge through a state of its own
+                let clash = graph.states[&from]
+                    .outs
+                    .get(&target)
+                    .is_some_and(|existing| *existing != t);
+                let target = if clash {
+                    let mut via = State::new(StateId(graph.last_id));
+                    graph.last_id += 1;
+                    via.outs.insert(target, Transition::Epsilon);
+                    let id = via.id;
+                    graph.states.insert(id, via);
+                    id
+                } else {
+                    target
+                };
+                graph.states.get_mut(&from).unwrap().outs.insert(target, t);
+            }
+        }
+        if graph.valid {
+            graph.trim();
+        }
+        (graph.prefix, graph.literal) = graph.literal_prefix();
+        graph.index_chars();
+        graph
+    }
+
+    // The edge taking the chars both `t` of `self` and `u` of `other` take,
+    // `None` when that can't be written as one edge.
+    fn meet(&self, t: &Transition, other: &NFAGraph, u: &Transition) -> Option<Transition> {
+        // the wildcard is a negated set of the line terminator chars
+        let as_set = |graph: &NFAGraph, t: &Transition| match t {
+            Transition::Any if graph.dot_matches_newline => Transition::NotChar(Vec::new()),
+            Transition::Any => Transition::NotChar(
+                ['\n', '\r']
+                    .into_iter()
+                    .filter(|c| graph.line_terminator.contains(*c))
+                    .collect(),
+            ),
+            t => t.clone(),
         };
-        for post_char in post.chars() {
-            match post_char {
-                '.' => {
-                    if stack.len() < 2 {
-                        return graph;
-                    }
-                    let frag2 = stack.pop().unwrap();
-                    let frag1 = stack.pop().unwrap();
-                    for next in frag1.end.iter() {
-                        let state = graph.states.get_mut(next).unwrap();
-                        state.outs.insert(frag2.start, Transition::Epsilon);
+        match (as_set(self, t), as_set(other, u)) {
+            (Transition::Char(chars), u) => Some(Transition::Char(
+                chars.into_iter().filter(|c| other.takes(&u, *c)).collect(),
+            )),
+            (t, Transition::Char(chars)) => Some(Transition::Char(
+                chars.into_iter().filter(|c| self.takes(&t, *c)).collect(),
+            )),
+            (Transition::NotChar(mut chars), Transition::NotChar(more)) => {
+                for c in more {
+                    if !chars.contains(&c) {
+                        chars.push(c);
                     }
+                }
+                Some(Transition::NotChar(chars))
+            }
+            (Transition::NotChar(chars), u) if chars.is_empty() => Some(u),
+            (t, Transition::NotChar(chars)) if chars.is_empty() => Some(t),
+            // a class edge that takes none of the chars the set leaves out,
+            // like `\p{L}` against the wildcard, is the intersection itself
+            (Transition::NotChar(chars), t) | (t, Transition::NotChar(chars))
+                if t.takes_char_only() && chars.iter().all(|c| !t.takes_char(*c)) =>
+            {
+                Some(t)
+            }
+            (Transition::Byte(bytes), Transition::Byte(more)) => Some(Transition::Byte(
+                bytes.into_iter().filter(|b| more.contains(b)).collect(),
+            )),
+            (t, u) if t == u => Some(t),
+            _ => None,
+        }
+    }
+
+    // Moves the states of `other` into the id space of `self`, shifting its
+    // ids past `last_id` and its capture slots past the groups of `self`.
+    // Returns the start, the accepting states and the alternative starts of
+    // `other` under their new ids.
+    fn absorb(&mut self, other: NFAGraph) -> (StateId, Vec<StateId>, Vec<StateId>) {
+        let offset = self.last_id;
+        let slots = 2 * self.group_names.len();
+        let shift = |id: StateId| StateId(id.0 + offset);
+        for (id, state) in other.states {
+            let mut moved = State::new(shift(id));
+            for (target, transition) in state.outs {
+                let transition = match transition {
+                    Transition::Save(slot) => Transition::Save(slot + slots),
+                    Transition::Counter(op) => Transition::Counter(op.shifted(self.counters)),
+                    t => t,
+                };
+                moved.outs.insert(shift(target), transition);
+            }
+            self.states.insert(moved.id, moved);
+        }
+        self.last_id += other.last_id;
+        self.valid &= other.valid;
+        self.group_names.extend(other.group_names);
+        self.counters += other.counters;
+        (
+            shift(other.start),
+            other.ends.into_iter().map(shift).collect(),
+            other.alternatives.into_iter().map(shift).collect(),
+        )
+    }
+
+    pub(crate) fn try_compile_subgraph(
+        post: &str,
+        graph: &mut NFAGraph,
+        config: &CompileConfig,
+    ) -> Result<(Frag, Vec<StateId>), RegexError> {
+        let mut tokens = postfix_tokens(post)?;
+        if !config.counted_loops {
+            tokens = expand_repeats(tokens, config.size_limit)?;
+        }
+        if config.trie && !config.bytes {
+            return Self::compile_tokens(&factor_words(tokens), graph, config);
+        }
+        Self::compile_tokens(&tokens, graph, config)
+    }
+
+    // Compiles postfix that is already split into tokens, e.g. built by hand
+    // without escaping literal operator chars.
+    pub fn from_tokens(tokens: &[PostfixToken]) -> Result<Self, RegexError> {
+        let mut graph = NFAGraph::empty();
+        let (frag, alternatives) =
+            Self::compile_tokens(tokens, &mut graph, &CompileConfig::default())?;
+        graph.start = frag.start;
+        graph.ends = frag.end;
+        graph.alternatives = alternatives;
+        (graph.prefix, graph.literal) = graph.literal_prefix();
+        graph.index_chars();
+        Ok(graph)
+    }
+
+    fn compile_tokens(
+        tokens: &[PostfixToken],
+        graph: &mut NFAGraph,
+        config: &CompileConfig,
+    ) -> Result<(Frag, Vec<StateId>), RegexError> {
+        let mut stack: Vec<Frag> = Vec::new();
+        // the run of `Alternate` ending the postfix joins the top-level
+        // alternatives, which are all on the stack right before it
+        let mut alternatives = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            if *token == PostfixToken::Alternate
+                && alternatives.is_empty()
+                && tokens[i..].iter().all(|t| *t == PostfixToken::Alternate)
+            {
+                alternatives = stack.iter().map(|frag| frag.start).collect();
+            }
+            match token {
+                PostfixToken::Concat => {
+                    let frag2 = pop(&mut stack)?;
+                    let frag1 = pop(&mut stack)?;
+                    Self::patch(graph, &frag1.end, frag2.start, Transition::Epsilon)?;
                     stack.push(Frag {
                         start: frag1.start,
                         end: frag2.end,
                     });
                 }
-                '|' => {
-                    if stack.len() < 2 {
-                        return graph;
-                    }
-                    let frag2 = stack.pop().unwrap();
-                    let frag1 = stack.pop().unwrap();
+                PostfixToken::Alternate => {
+                    let frag2 = pop(&mut stack)?;
+                    let frag1 = pop(&mut stack)?;
                     let mut start = State::new(StateId(graph.last_id));
                     let end = State::new(StateId(graph.last_id + 1));
                     graph.last_id += 2;
                     start.outs.insert(frag1.start, Transition::Epsilon);
                     start.outs.insert(frag2.start, Transition::Epsilon);
-                    for next in frag1.end.iter() {
-                        let state = graph.states.get_mut(next).unwrap();
-                        state.outs.insert(end.id, Transition::Epsilon);
-                    }
-                    for next in frag2.end.iter() {
-                        let state = graph.states.get_mut(next).unwrap();
-                        state.outs.insert(end.id, Transition::Epsilon);
-                    }
+                    Self::patch(graph, &frag1.end, end.id, Transition::Epsilon)?;
+                    Self::patch(graph, &frag2.end, end.id, Transition::Epsilon)?;
                     graph.states.insert(start.id, start.clone());
                     graph.states.insert(end.id, end.clone());
                     stack.push(Frag {
@@ -99,35 +1192,14 @@ impl NFAGraph {
                         end: vec![end.id],
                     });
                 }
-                '?' => {
-                    if stack.is_empty() {
-                        return graph;
-                    }
-                    let frag = stack.pop().unwrap();
-                    let start = graph.states.get_mut(&frag.start).unwrap();
-                    frag.end.iter().for_each(|e| {
-                        start.outs.insert(*e, Transition::Epsilon);
-                    });
-                    stack.push(frag);
-                }
-                '*' => {
-                    if stack.is_empty() {
-                        return graph;
-                    }
-                    let frag = stack.pop().unwrap();
+                PostfixToken::Optional(lazy) => {
+                    let frag = pop(&mut stack)?;
                     let mut start = State::new(StateId(graph.last_id));
                     let end = State::new(StateId(graph.last_id + 1));
                     graph.last_id += 2;
-                    start.outs.insert(frag.start, Transition::Epsilon);
-                    let old_start = graph.states.get_mut(&frag.start).unwrap();
-                    for next in frag.end.iter() {
-                        old_start.outs.insert(*next, Transition::Epsilon);
-                    }
-                    for next in frag.end.iter() {
-                        let state = graph.states.get_mut(next).unwrap();
-                        state.outs.insert(end.id, Transition::Epsilon);
-                        state.outs.insert(frag.start, Transition::Epsilon);
-                    }
+                    start.outs.insert(frag.start, Transition::enter(*lazy));
+                    start.outs.insert(end.id, Transition::Epsilon);
+                    Self::patch(graph, &frag.end, end.id, Transition::Epsilon)?;
                     graph.states.insert(start.id, start.clone());
                     graph.states.insert(end.id, end.clone());
                     stack.push(Frag {
@@ -135,20 +1207,30 @@ impl NFAGraph {
                         end: vec![end.id],
                     });
                 }
-                '+' => {
-                    if stack.is_empty() {
-                        return graph;
-                    }
-                    let frag = stack.pop().unwrap();
+                PostfixToken::Star(lazy) => {
+                    let frag = pop(&mut stack)?;
+                    let mut start = State::new(StateId(graph.last_id));
+                    let end = State::new(StateId(graph.last_id + 1));
+                    graph.last_id += 2;
+                    start.outs.insert(frag.start, Transition::enter(*lazy));
+                    start.outs.insert(end.id, Transition::Epsilon);
+                    Self::patch(graph, &frag.end, end.id, Transition::Epsilon)?;
+                    Self::patch(graph, &frag.end, frag.start, Transition::enter(*lazy))?;
+                    graph.states.insert(start.id, start.clone());
+                    graph.states.insert(end.id, end.clone());
+                    stack.push(Frag {
+                        start: start.id,
+                        end: vec![end.id],
+                    });
+                }
+                PostfixToken::Plus(lazy) => {
+                    let frag = pop(&mut stack)?;
                     let mut start = State::new(StateId(graph.last_id));
                     let end = State::new(StateId(graph.last_id + 1));
                     graph.last_id += 2;
                     start.outs.insert(frag.start, Transition::Epsilon);
-                    for next in frag.end.iter() {
-                        let state = graph.states.get_mut(next).unwrap();
-                        state.outs.insert(end.id, Transition::Epsilon);
-                        state.outs.insert(frag.start, Transition::Epsilon);
-                    }
+                    Self::patch(graph, &frag.end, end.id, Transition::Epsilon)?;
+                    Self::patch(graph, &frag.end, frag.start, Transition::enter(*lazy))?;
                     graph.states.insert(start.id, start.clone());
                     graph.states.insert(end.id, end.clone());
                     stack.push(Frag {
@@ -156,263 +1238,3916 @@ impl NFAGraph {
                         end: vec![end.id],
                     });
                 }
-                c if c.is_alphanumeric() => {
+                PostfixToken::Group(group) => {
+                    let frag = pop(&mut stack)?;
                     let mut start = State::new(StateId(graph.last_id));
                     let end = State::new(StateId(graph.last_id + 1));
                     graph.last_id += 2;
-                    start.outs.insert(end.id, Transition::Char(vec![c]));
+                    start.outs.insert(frag.start, Transition::Save(2 * group));
+                    Self::patch(graph, &frag.end, end.id, Transition::Save(2 * group + 1))?;
+                    graph.states.insert(start.id, start.clone());
+                    graph.states.insert(end.id, end.clone());
+                    stack.push(Frag {
+                        start: start.id,
+                        end: vec![end.id],
+                    });
+                }
+                PostfixToken::Literal(c) => {
+                    stack.push(Self::literal_frag(graph, *c, config));
+                }
+                PostfixToken::Set(members, negated) => {
+                    stack.push(Self::class_frag(graph, members.clone(), *negated, config)?);
+                }
+                PostfixToken::Class(id, negated) => {
+                    if config.bytes {
+                        return Err(RegexError::Unsupported("Unicode classes in bytes mode"));
+                    }
+                    let transition = if *negated {
+                        Transition::NotClass(vec![*id], Vec::new())
+                    } else {
+                        Transition::Class(*id)
+                    };
+                    stack.push(Self::edge_frag(graph, transition));
+                }
+                PostfixToken::Edge(transition) => {
+                    if config.bytes && matches!(transition, Transition::NotClass(..)) {
+                        return Err(RegexError::Unsupported("Unicode classes in bytes mode"));
+                    }
+                    stack.push(Self::edge_frag(graph, transition.clone()));
+                }
+                PostfixToken::Words(words) => {
+                    stack.push(Self::trie_frag(graph, words));
+                }
+                PostfixToken::NotBefore => {
+                    // the operand is a lone char edge, which is replaced
+                    let frag = pop(&mut stack)?;
+                    let edge = graph
+                        .states
+                        .remove(&frag.start)
+                        .and_then(|state| state.outs.into_iter().next())
+                        .filter(|(end, t)| frag.end == [*end] && t.takes_char_only())
+                        .ok_or(RegexError::MalformedPostfix)?;
+                    graph.states.remove(&edge.0);
+                    let assertion = Assertion::NotBefore(Box::new(edge.1));
+                    stack.push(Self::edge_frag(graph, Transition::Assert(assertion)));
+                }
+                PostfixToken::Repeat(min, max) => {
+                    // without `counted_loops`, `expand_repeats` has replaced
+                    // these by copies of their operands
+                    let frag = pop(&mut stack)?;
+                    let counter = graph.counters;
+                    graph.counters += 1;
+                    let mut start = State::new(StateId(graph.last_id));
+                    let mut again = State::new(StateId(graph.last_id + 1));
+                    let end = State::new(StateId(graph.last_id + 2));
+                    graph.last_id += 3;
+                    if *max != Some(0) {
+                        let reset = Transition::Counter(CounterOp::Reset(counter));
+                        start.outs.insert(frag.start, reset);
+                    }
+                    if *min == 0 {
+                        start.outs.insert(end.id, Transition::Epsilon);
+                    }
+                    let (min, max) = (*min, *max);
+                    let op = CounterOp::Again { counter, min, max };
+                    again.outs.insert(frag.start, Transition::Counter(op));
+                    let op = CounterOp::Done { counter, min };
+                    again.outs.insert(end.id, Transition::Counter(op));
+                    Self::patch(graph, &frag.end, again.id, Transition::Epsilon)?;
                     graph.states.insert(start.id, start.clone());
+                    graph.states.insert(again.id, again);
                     graph.states.insert(end.id, end.clone());
                     stack.push(Frag {
                         start: start.id,
                         end: vec![end.id],
                     });
                 }
-                _ => {
-                    panic!("illegal character")
+                PostfixToken::Start | PostfixToken::End => {
+                    let assertion = match (token, config.multiline) {
+                        (PostfixToken::Start, false) => Assertion::StartText,
+                        (PostfixToken::Start, true) => Assertion::StartLine(config.line_terminator),
+                        (_, false) => Assertion::EndText,
+                        (_, true) => Assertion::EndLine(config.line_terminator),
+                    };
+                    stack.push(Self::edge_frag(graph, Transition::Assert(assertion)));
                 }
             }
+            // every token allocates at most a handful of states, so checking
+            // once per token keeps the graph within the limit plus a constant
+            if graph.last_id > config.size_limit {
+                return Err(RegexError::SizeLimitExceeded(config.size_limit));
+            }
         }
-        if !stack.is_empty() {
-            let frag = stack.pop().unwrap();
-            graph.start = frag.start;
-            graph.ends = frag.end;
+        if stack.len() > 1 {
+            return Err(RegexError::MalformedPostfix);
+        }
+        match stack.pop() {
+            Some(frag) => Ok((frag, alternatives)),
+            None => {
+                // the empty pattern only accepts at its start state
+                let id = StateId(graph.last_id);
+                graph.last_id += 1;
+                graph.states.insert(id, State::new(id));
+                Ok((
+                    Frag {
+                        start: id,
+                        end: vec![id],
+                    },
+                    alternatives,
+                ))
+            }
         }
-        graph
     }
 
-    pub fn is_match(&self, s: &str) -> bool {
-        self.check_match(s, self.start)
+    // Adds an edge to `target` from each of the dangling `ends` of a
+    // fragment. They normally all exist, but `compile_subgraph` takes any
+    // graph, so a missing one is reported rather than trusted.
+    fn patch(
+        graph: &mut NFAGraph,
+        ends: &[StateId],
+        target: StateId,
+        transition: Transition,
+    ) -> Result<(), RegexError> {
+        for end in ends {
+            let state = graph
+                .states
+                .get_mut(end)
+                .ok_or(RegexError::MalformedPostfix)?;
+            state.outs.insert(target, transition.clone());
+        }
+        Ok(())
     }
 
-    fn check_match(&self, s: &str, state_id: StateId) -> bool {
-        let mut current_set = vec![state_id];
-        let mut next_set = self.closure(current_set);
-        for (i, c) in s.chars().enumerate() {
-            current_set = self.move2(c, &next_set);
-            next_set = self.closure(current_set);
+    // A fragment matching the literal `c`: a single char edge, or in bytes
+    // mode a chain of byte edges over its UTF-8 encoding.
+    fn literal_frag(graph: &mut NFAGraph, c: char, config: &CompileConfig) -> Frag {
+        if !config.bytes {
+            return Self::edge_frag(graph, Transition::Char(vec![c]));
+        }
+        let start = StateId(graph.last_id);
+        graph.last_id += 1;
+        let mut state = State::new(start);
+        let mut buf = [0u8; 4];
+        for b in c.encode_utf8(&mut buf).bytes() {
+            let next = StateId(graph.last_id);
+            graph.last_id += 1;
+            state.outs.insert(next, Transition::Byte(vec![b]));
+            graph.states.insert(state.id, state);
+            state = State::new(next);
+        }
+        let end = state.id;
+        graph.states.insert(end, state);
+        Frag {
+            start,
+            end: vec![end],
+        }
+    }
 
-            if next_set.is_empty() {
-                return false;
+    // A fragment matching one char of a bracket class. Bytes mode only has
+    // single-byte edges to offer, so classes there have to be ASCII.
+    fn class_frag(
+        graph: &mut NFAGraph,
+        members: Vec<char>,
+        negated: bool,
+        config: &CompileConfig,
+    ) -> Result<Frag, RegexError> {
+        let transition = if !config.bytes {
+            if negated {
+                Transition::NotChar(members)
+            } else {
+                Transition::Char(members)
             }
+        } else if !negated && members.iter().all(|c| c.is_ascii()) {
+            Transition::Byte(members.into_iter().map(|c| c as u8).collect())
+        } else {
+            return Err(RegexError::Unsupported("non-ASCII classes in bytes mode"));
+        };
+        Ok(Self::edge_frag(graph, transition))
+    }
 
-            for state_id in next_set.iter() {
-                let state = self.states.get(state_id).unwrap();
-                if state.outs.is_empty() && i == s.len() - 1 {
-                    return true;
-                }
+    // A fragment matching any of `words`, with one state per distinct
+    // prefix. The words are never empty.
+    fn trie_frag(graph: &mut NFAGraph, words: &[String]) -> Frag {
+        let root = State::new(StateId(graph.last_id));
+        graph.last_id += 1;
+        let start = root.id;
+        graph.states.insert(root.id, root);
+        let mut children: HashMap<(StateId, char), StateId> = HashMap::new();
+        let mut end = Vec::new();
+        for word in words {
+            let mut node = start;
+            for c in word.chars() {
+                node = *children.entry((node, c)).or_insert_with(|| {
+                    let child = StateId(graph.last_id);
+                    graph.last_id += 1;
+                    graph.states.insert(child, State::new(child));
+                    graph
+                        .states
+                        .get_mut(&node)
+                        .unwrap()
+                        .outs
+                        .insert(child, Transition::Char(vec![c]));
+                    child
+                });
+            }
+            if !end.contains(&node) {
+                end.push(node);
             }
         }
-        false
+        Frag { start, end }
     }
 
-    fn closure(&self, current_set: Vec<StateId>) -> Vec<StateId> {
-        let mut closure_set = current_set.clone();
-        let mut queue = VecDeque::new();
-        for cl in current_set {
-            queue.push_back(cl);
+    // A fragment of two fresh states joined by a single edge.
+    fn edge_frag(graph: &mut NFAGraph, transition: Transition) -> Frag {
+        let mut start = State::new(StateId(graph.last_id));
+        let end = State::new(StateId(graph.last_id + 1));
+        graph.last_id += 2;
+        start.outs.insert(end.id, transition);
+        let frag = Frag {
+            start: start.id,
+            end: vec![end.id],
+        };
+        graph.states.insert(start.id, start);
+        graph.states.insert(end.id, end);
+        frag
+    }
+
+    // Drops states that can't be reached from the start state or can't reach
+    // an accepting state, then renumbers the rest densely in id order. The
+    // start state and the alternative starts are always kept so `find_tagged`
+    // keeps its numbering. An invalid graph, or one whose start state is
+    // missing, is left as it is.
+    pub fn trim(&mut self) {
+        if !self.valid || !self.states.contains_key(&self.start) {
+            return;
         }
-        while !queue.is_empty() {
-            let state_id = queue.pop_front().unwrap();
-            let state = self.states.get(&state_id).unwrap();
-            for out in state.outs.iter() {
-                if let Transition::Epsilon = out.1 {
-                    if !closure_set.contains(out.0) {
-                        closure_set.push(*out.0);
-                        queue.push_back(*out.0);
-                    }
+        let mut reachable = HashSet::new();
+        let mut stack = vec![self.start];
+        while let Some(id) = stack.pop() {
+            if reachable.insert(id) {
+                if let Some(state) = self.states.get(&id) {
+                    stack.extend(state.outs.keys().copied());
                 }
             }
         }
-        closure_set
-    }
+        let mut reverse: HashMap<StateId, Vec<StateId>> = HashMap::new();
+        for state in self.states.values() {
+            for target in state.outs.keys() {
+                reverse.entry(*target).or_default().push(state.id);
+            }
+        }
+        let mut live = HashSet::new();
+        let mut stack: Vec<StateId> = self
+            .ends
+            .iter()
+            .copied()
+            .filter(|id| self.states.contains_key(id))
+            .collect();
+        while let Some(id) = stack.pop() {
+            if live.insert(id) {
+                stack.extend(reverse.get(&id).into_iter().flatten().copied());
+            }
+        }
 
-    fn move2(&self, c: char, current_set: &[StateId]) -> Vec<StateId> {
-        let mut next_set = Vec::new();
-        for state_id in current_set.iter() {
-            let state = self.states.get(state_id).unwrap();
-            for out in state.outs.iter() {
-                if let Transition::Char(chars) = out.1 {
-                    if chars.contains(&c) {
-                        next_set.push(*out.0);
-                    }
+        let mut kept: Vec<StateId> = reachable
+            .intersection(&live)
+            .copied()
+            .filter(|id| self.states.contains_key(id))
+            .collect();
+        kept.push(self.start);
+        kept.extend(
+            self.alternatives
+                .iter()
+                .copied()
+                .filter(|id| self.states.contains_key(id)),
+        );
+        kept.sort();
+        kept.dedup();
+        let remap: HashMap<StateId, StateId> = kept
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (*id, StateId(i)))
+            .collect();
+        let mut states = HashMap::with_capacity(kept.len());
+        for id in kept.iter() {
+            let mut state = State::new(remap[id]);
+            for (target, transition) in self.states[id].outs.iter() {
+                if let Some(target) = remap.get(target) {
+                    state.outs.insert(*target, transition.clone());
                 }
             }
+            states.insert(state.id, state);
         }
-        next_set
+        self.states = states;
+        self.last_id = kept.len();
+        self.start = remap[&self.start];
+        self.ends = self
+            .ends
+            .iter()
+            .filter_map(|id| remap.get(id))
+            .copied()
+            .collect();
+        self.alternatives = self
+            .alternatives
+            .iter()
+            .filter_map(|id| remap.get(id))
+            .copied()
+            .collect();
+        self.index_chars();
     }
 
-    pub fn display(&self) {
-        for state in self.states.iter() {
-            println!("state id: {:?}, state outs: {:?}", state.0 .0, state.1.outs)
+    // Removes states whose only edge is a plain epsilon edge, pointing their
+    // incoming edges straight at its target instead. A state is kept when
+    // it's accepting, an alternative start, or when moving an incoming edge
+    // would clash with an edge its source already has or change the id order
+    // of that source's targets, which `captures` uses as edge priority.
+    pub fn coalesce_epsilons(&mut self) {
+        let mut ids: Vec<StateId> = self.states.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            let target = match self.states[&id].outs.iter().next() {
+                Some((target, Transition::Epsilon)) if self.states[&id].outs.len() == 1 => *target,
+                _ => continue,
+            };
+            if target == id || self.is_accepting(&id) || self.alternatives.contains(&id) {
+                continue;
+            }
+            let preds: Vec<(StateId, Transition)> = self
+                .states
+                .values()
+                .filter_map(|state| state.outs.get(&id).map(|t| (state.id, t.clone())))
+                .collect();
+            let safe = preds.iter().all(|(pred, transition)| {
+                self.states[pred].outs.iter().all(|(other, t)| {
+                    if *other == target {
+                        t == transition
+                    } else {
+                        *other == id || (*other < id) == (*other < target)
+                    }
+                })
+            });
+            if !safe {
+                continue;
+            }
+            for (pred, transition) in preds {
+                let state = self.states.get_mut(&pred).unwrap();
+                state.outs.remove(&id);
+                state.outs.insert(target, transition);
+            }
+            if self.start == id {
+                self.start = target;
+            }
+            self.states.remove(&id);
         }
+        self.index_chars();
     }
-}
 
-pub fn re2post(re: &str) -> Option<String> {
-    let mut postfix: String = String::new();
-    struct Paren {
-        natom: usize,
-        nalt: usize,
+    // Rebuilds the index `move2` uses for `Char` edges and the reversed
+    // graph `search` uses. Needed again after editing `states` by hand.
+    pub fn index_chars(&mut self) {
+        self.index_own_chars();
+        self.index_reversed();
     }
-    let mut paren: Vec<Paren> = Vec::new();
-    let mut natom = 0usize;
-    let mut nalt = 0usize;
-    for re_char in re.chars() {
-        match re_char {
-            '(' => {
-                if natom > 1 {
-                    natom -= 1;
-                    postfix.push('.');
+
+    // Rebuilds the reversed graph alone, e.g. after changing the options it
+    // copies from this one.
+    pub(crate) fn index_reversed(&mut self) {
+        self.reversed = (self.counters == 0).then(|| {
+            let mut reversed = self.reverse();
+            reversed.index_own_chars();
+            Box::new(reversed)
+        });
+    }
+
+    fn index_own_chars(&mut self) {
+        self.char_index.clear();
+        for state in self.states.values() {
+            let mut by_char: HashMap<char, Vec<StateId>> = HashMap::new();
+            for (target, transition) in state.outs.iter() {
+                if let Transition::Char(chars) = transition {
+                    for c in chars.iter() {
+                        by_char.entry(*c).or_default().push(*target);
+                    }
                 }
-                paren.push(Paren { natom, nalt });
-                natom = 0;
-                nalt = 0;
             }
-            '|' => {
-                nalt += 1;
-                if natom == 0 {
-                    return None;
-                }
-                while natom > 1 {
-                    natom -= 1;
-                    postfix.push('.');
-                }
-                if natom == 1 {
-                    natom = 0;
-                }
+            if !by_char.is_empty() {
+                self.char_index.insert(state.id, by_char);
             }
-            ')' => {
-                if paren.is_empty() {
-                    return None;
-                }
-                if natom == 0 {
-                    return None;
-                }
-                while natom > 1 {
-                    natom -= 1;
-                    postfix.push('.');
-                }
-                while nalt > 0 {
-                    nalt -= 1;
-                    postfix.push('|');
-                }
-                let p = paren.pop().unwrap();
-                natom = p.natom + 1;
-                nalt = p.nalt;
+        }
+    }
+
+    // Number of capture groups, not counting the implicit whole-match group.
+    pub fn capture_count(&self) -> usize {
+        self.group_names.len()
+    }
+
+    // The name of every capture group in order, `None` for unnamed ones.
+    pub fn capture_names(&self) -> Vec<Option<String>> {
+        self.group_names.clone()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    // Whether the input is brought into NFC before matching, see
+    // `RegexBuilder::unicode_normalize`.
+    pub fn normalized(&self) -> bool {
+        #[cfg(feature = "unicode-normalization")]
+        return self.normalize;
+        #[cfg(not(feature = "unicode-normalization"))]
+        false
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        if self.literal {
+            return s == self.prefix;
+        }
+        self.valid && self.check_match(s.chars(), self.start)
+    }
+
+    // `is_match` over any source of chars, e.g. a decoder that never builds
+    // a `&str`. Assertions look at the chars around each position as usual.
+    pub fn is_match_chars<I: IntoIterator<Item = char>>(&self, chars: I) -> bool {
+        self.valid && self.check_match(chars, self.start)
+    }
+
+    // `is_match` for every input, spread over the rayon thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn par_matches(&self, inputs: &[String]) -> Vec<bool> {
+        use rayon::prelude::*;
+        inputs.par_iter().map(|s| self.is_match(s)).collect()
+    }
+
+    // Whether some match starts exactly at byte offset `start`. It may end
+    // anywhere, and the text before `start` only matters to assertions. An
+    // offset that isn't a char boundary never matches.
+    pub fn is_match_at(&self, s: &str, start: usize) -> bool {
+        self.valid && s.is_char_boundary(start) && self.longest_match_at(s, start).is_some()
+    }
+
+    // Maximal munch: the byte length of the longest prefix of `s` the
+    // pattern accepts, `Some(0)` if only the empty one. Unlike `find`, lazy
+    // quantifiers make no difference.
+    pub fn longest_prefix(&self, s: &str) -> Option<usize> {
+        if !self.valid {
+            return None;
+        }
+        let mut current = self.start_threads(self.start, Context::at(s, 0));
+        let mut longest = self.accepts(&current).then_some(0);
+        for (i, c) in s.char_indices() {
+            let end = i + c.len_utf8();
+            current = self.step(&current, c, Context::at(s, end));
+            if current.is_empty() {
+                break;
             }
-            '*' | '+' | '?' => {
-                if natom == 0 {
-                    return None;
-                }
-                postfix.push(re_char);
+            if self.accepts(&current) {
+                longest = Some(end);
             }
-            c if c.is_alphanumeric() => {
-                if natom > 1 {
-                    natom -= 1;
-                    postfix.push('.');
-                }
-                postfix.push(c);
-                natom += 1;
+        }
+        longest
+    }
+
+    // The byte length of the shortest prefix of `s` the pattern accepts, so
+    // a lexer can stop at the first complete token instead of running on
+    // like `longest_prefix`. `Some(0)` if the empty prefix is accepted.
+    pub fn shortest_prefix(&self, s: &str) -> Option<usize> {
+        if !self.valid {
+            return None;
+        }
+        let mut current = self.start_threads(self.start, Context::at(s, 0));
+        if self.accepts(&current) {
+            return Some(0);
+        }
+        for (i, c) in s.char_indices() {
+            let end = i + c.len_utf8();
+            current = self.step(&current, c, Context::at(s, end));
+            if current.is_empty() {
+                return None;
             }
-            _ => {
-                panic!("illegal character")
+            if self.accepts(&current) {
+                return Some(end);
             }
         }
+        None
     }
-    // Parentheses do not come in pairs. It's an error.
-    if !paren.is_empty() {
-        return None;
+
+    // Whether consuming all of `s` from the start state ends in an accepting
+    // state, i.e. the pattern anchored at both ends whether or not it
+    // contains `^`/`$`. Use `find` to look for a match inside `s`.
+    pub fn is_full_match(&self, s: &str) -> bool {
+        // `check_match` starts at the start state and only accepts once the
+        // last char is consumed
+        self.valid && self.check_match(s.chars(), self.start)
     }
-    while natom > 1 {
-        natom -= 1;
-        postfix.push('.');
+
+    // Whether the pattern accepts the empty string, e.g. to reject a `split`
+    // delimiter that would match between every pair of chars. Assertions
+    // are checked against the empty input, so `^$` accepts it and `\b`
+    // doesn't.
+    pub fn matches_empty(&self) -> bool {
+        self.valid && self.accepts(&self.start_threads(self.start, Context::at("", 0)))
     }
-    while nalt > 0 {
-        nalt -= 1;
-        postfix.push('|');
+
+    pub fn try_is_match(&self, s: &str) -> Result<bool, MatchError> {
+        if !self.valid {
+            return Err(MatchError::InvalidGraph);
+        }
+        self.check_match_within(s.chars(), self.start, usize::MAX)
     }
-    Some(postfix)
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+    // Like `is_match`, but gives up once more than `max_steps` characters
+    // would have to be processed.
+    pub fn is_match_within(&self, s: &str, max_steps: usize) -> Result<bool, MatchError> {
+        if !self.valid {
+            return Err(MatchError::InvalidGraph);
+        }
+        self.check_match_within(s.chars(), self.start, max_steps)
+    }
 
-    use crate::nfa::StateId;
+    fn check_match<I: IntoIterator<Item = char>>(&self, chars: I, state_id: StateId) -> bool {
+        // running into the active state limit counts as no match
+        self.check_match_within(chars, state_id, usize::MAX)
+            .unwrap_or(false)
+    }
 
-    #[test]
-    fn test_re_2_post() {
-        assert_eq!("a+b+.", super::re2post("a+b+").unwrap_or_default());
-        assert_eq!(
-            "azd.c.e||+b+.",
-            super::re2post("(a|zdc|e)+b+").unwrap_or_default()
-        );
-        assert_eq!(
-            "azd*.c+.e||+b+.",
-            super::re2post("(a|zd*c+|e)+b+").unwrap_or_default()
-        );
+    fn check_match_within<I: IntoIterator<Item = char>>(
+        &self,
+        chars: I,
+        state_id: StateId,
+        max_steps: usize,
+    ) -> Result<bool, MatchError> {
+        #[cfg(feature = "unicode-normalization")]
+        if self.normalize {
+            use unicode_normalization::UnicodeNormalization;
+            return self.run_match_within(chars.into_iter().nfc(), state_id, max_steps);
+        }
+        self.run_match_within(chars, state_id, max_steps)
     }
 
-    #[test]
-    pub fn test_nfa() {
-        let pattern = "a+b+";
-        let post = super::re2post(pattern).unwrap_or_default();
-        assert_eq!("a+b+.", post);
-        let graph = super::NFAGraph::compile(&post);
-        assert_eq!(graph.states.len(), 8);
-        graph.display();
-        println!(
-            "state {} next is {:?}",
-            3,
-            graph.states.get(&StateId(3)).unwrap().outs
-        );
-        {
-            let mut map = HashMap::new();
-            map.insert(StateId(0), super::Transition::Epsilon);
-            map.insert(StateId(3), super::Transition::Epsilon);
-            assert_eq!(map, graph.states.get(&StateId(1)).unwrap().outs);
+    fn run_match_within<I: IntoIterator<Item = char>>(
+        &self,
+        chars: I,
+        state_id: StateId,
+        max_steps: usize,
+    ) -> Result<bool, MatchError> {
+        let mut chars = chars.into_iter().peekable();
+        let ctx = Context {
+            prev: None,
+            next: chars.peek().copied(),
+        };
+        let mut current = self.start_threads(state_id, ctx);
+        if current.len() > self.active_state_limit {
+            return Err(MatchError::TooManyStates);
         }
-        {
-            let mut map = HashMap::new();
-            map.insert(StateId(4), super::Transition::Epsilon);
-            map.insert(StateId(7), super::Transition::Epsilon);
-            assert_eq!(map, graph.states.get(&StateId(5)).unwrap().outs);
+        if ctx.next.is_none() {
+            return Ok(self.accepts(&current));
         }
+        let mut i = 0;
+        while let Some(c) = chars.next() {
+            if i >= max_steps {
+                return Err(MatchError::StepLimitExceeded);
+            }
+            i += 1;
+            let ctx = Context {
+                prev: Some(c),
+                next: chars.peek().copied(),
+            };
+            current = self.step(&current, c, ctx);
+            if current.len() > self.active_state_limit {
+                return Err(MatchError::TooManyStates);
+            }
 
-        let pattern = "a(b|c)*";
-        let post = super::re2post(pattern).unwrap_or_default();
-        assert_eq!("abc|*.", post);
-        let graph = super::NFAGraph::compile(&post);
-        graph.display();
-        {
-            let mut map = HashMap::new();
-            map.insert(StateId(6), super::Transition::Epsilon);
-            map.insert(StateId(9), super::Transition::Epsilon);
-            assert_eq!(map, graph.states.get(&StateId(7)).unwrap().outs);
-        }
-        {
-            let mut map = HashMap::new();
-            map.insert(StateId(4), super::Transition::Epsilon);
-            map.insert(StateId(2), super::Transition::Epsilon);
-            map.insert(StateId(7), super::Transition::Epsilon);
-            assert_eq!(map, graph.states.get(&StateId(6)).unwrap().outs);
+            if current.is_empty() {
+                return Ok(false);
+            }
+
+            if ctx.next.is_none() && self.accepts(&current) {
+                return Ok(true);
+            }
         }
-        assert_eq!(graph.states.len(), 10);
+        Ok(false)
     }
 
-    #[test]
-    pub fn test_match() {
-        {
-            let pattern = "a+b+";
-            let post = super::re2post(pattern).unwrap_or_default();
-            let graph = super::NFAGraph::compile(&post);
-            assert_eq!(graph.is_match("aaaabbb"), true);
-            let pattern = "a(b|c)*";
-            let post = super::re2post(pattern).unwrap_or_default();
-            let graph = super::NFAGraph::compile(&post);
-            assert_eq!(graph.is_match("abbcbbcc"), true);
-            assert_eq!(graph.is_match("bcbbcc"), false);
+    // Matches raw bytes against a graph compiled in bytes mode, so the input
+    // doesn't have to be valid UTF-8.
+    pub fn is_match_bytes(&self, s: &[u8]) -> bool {
+        if !self.valid {
+            return false;
+        }
+        // assertions only look at ASCII neighbours in bytes mode
+        let ctx = |i: usize| Context {
+            prev: i.checked_sub(1).map(|j| s[j] as char),
+            next: s.get(i).map(|b| *b as char),
+        };
+        let mut next_set = self.start_threads(self.start, ctx(0));
+        for (i, b) in s.iter().enumerate() {
+            let moved = self.advance(&next_set, |ids| self.move_byte(*b, ids));
+            next_set = self.close(moved, ctx(i + 1));
+
+            if next_set.is_empty() {
+                return false;
+            }
         }
+        self.accepts(&next_set)
+    }
+
+    // Leftmost-longest search: the earliest start position that matches,
+    // extended as far as the pattern allows. Lazy quantifiers are honored
+    // here (fewer lazy repetitions beat a longer match), while `is_match`
+    // ignores laziness since it doesn't change whether a match exists.
+    pub fn find(&self, s: &str) -> Option<Match> {
+        self.find_at(s, 0)
+    }
+
+    pub fn find_at(&self, s: &str, start: usize) -> Option<Match> {
+        self.search(s, start, |at| self.longest_match_at(s, at))
+    }
+
+    // Leftmost-shortest search: the earliest start position that matches,
+    // ending at the first offset where the pattern accepts.
+    pub fn find_shortest(&self, s: &str) -> Option<Match> {
+        self.search(s, 0, |at| self.shortest_match_at(s, at))
+    }
+
+    // `is_match` for text that isn't one `&str`, like a rope.
+    pub fn is_match_source<T: TextSource + ?Sized>(&self, text: &T) -> bool {
+        self.is_match_chars(text.chars_from(0))
+    }
+
+    // `find` for text that isn't one `&str`. The text is only read through
+    // `chars_from`, so every start position is tried in turn without the
+    // prefix skipping and the bound from the reversed graph that `find`
+    // uses on a `&str`.
+    pub fn find_source<T: TextSource + ?Sized>(&self, text: &T) -> Option<Match> {
+        if !self.valid {
+            return None;
+        }
+        let mut at = 0;
+        let mut prev = None;
+        loop {
+            if let Some(end) = self.longest_match_chars(text.chars_from(at), at, prev) {
+                return Some(Match { start: at, end });
+            }
+            if self.anchored || at >= text.len() {
+                return None;
+            }
+            let c = text.chars_from(at).next()?;
+            at += c.len_utf8();
+            prev = Some(c);
+        }
+    }
+
+    // Tries `match_at` at every start position from `start` on and returns
+    // the first span it reports. An anchored graph only tries `start`.
+    fn search(
+        &self,
+        s: &str,
+        start: usize,
+        match_at: impl Fn(usize) -> Option<usize>,
+    ) -> Option<Match> {
+        if !self.valid || start > s.len() || !s.is_char_boundary(start) {
+            return None;
+        }
+        if self.anchored {
+            return match_at(start).map(|end| Match { start, end });
+        }
+        if self.literal {
+            let at = start + s[start..].find(self.prefix.as_str())?;
+            return Some(Match {
+                start: at,
+                end: at + self.prefix.len(),
+            });
+        }
+        let mut at = start;
+        // a match can only start where the required prefix occurs, so a
+        // substring search skips every other position without running the
+        // simulation there
+        if !self.prefix.is_empty() {
+            at += s[at..].find(self.prefix.as_str())?;
+        }
+        // One unanchored forward pass finds where the first match ends, or
+        // that there is none. Running the reversed graph back from there
+        // finds a start that is known to match, so the anchored attempts
+        // below stop there at the latest.
+        let first_end = self.earliest_end(s, at)?;
+        // a reversed counted loop would count in the wrong places, so those
+        // graphs try every start up to the match. The stored reversed graph
+        // is only right while the wildcard options are the ones it copied.
+        let bound = match &self.reversed {
+            _ if self.counters > 0 => first_end,
+            Some(reversed)
+                if reversed.dot_matches_newline == self.dot_matches_newline
+                    && reversed.line_terminator == self.line_terminator =>
+            {
+                reversed.leftmost_start(s, at, first_end)
+            }
+            _ => self.reverse().leftmost_start(s, at, first_end),
+        };
+        while at <= bound {
+            if let Some(end) = match_at(at) {
+                return Some(Match { start: at, end });
+            }
+            at += s[at..].chars().next()?.len_utf8();
+            if !self.prefix.is_empty() {
+                at += s[at..].find(self.prefix.as_str())?;
+            }
+        }
+        None
+    }
+
+    // End offset of the match that ends first, over all starts from `start`.
+    fn earliest_end(&self, s: &str, start: usize) -> Option<usize> {
+        let mut current = self.start_threads(self.start, Context::at(s, start));
+        if self.accepts(&current) {
+            return Some(start);
+        }
+        for (i, c) in s[start..].char_indices() {
+            let end = start + i + c.len_utf8();
+            // a new thread starts at every position
+            let mut next = self.advance(&current, |ids| self.move2(c, ids));
+            match &mut next {
+                Threads::States(ids) => ids.push(self.start),
+                Threads::Counted(threads) => threads.push((self.start, vec![0; self.counters])),
+            }
+            current = self.close(next, Context::at(s, end));
+            if self.accepts(&current) {
+                return Some(end);
+            }
+        }
+        None
+    }
+
+    // Run on a reversed graph: the leftmost offset in `start..=end` from
+    // which the original graph matches exactly up to `end`, or `end` if
+    // there is none.
+    fn leftmost_start(&self, s: &str, start: usize, end: usize) -> usize {
+        // assertions were swapped by `reverse`, so prev and next are too
+        let ctx = |at: usize| {
+            let ctx = Context::at(s, at);
+            Context {
+                prev: ctx.next,
+                next: ctx.prev,
+            }
+        };
+        let mut best = end;
+        let mut current = self.closure(vec![self.start], ctx(end));
+        for (i, c) in s[start..end].char_indices().rev() {
+            let at = start + i;
+            current = self.closure(self.move2(c, &current), ctx(at));
+            if current.is_empty() {
+                break;
+            }
+            if current.iter().any(|id| self.is_accepting(id)) {
+                best = at;
+            }
+        }
+        best
+    }
+
+    // The graph of the reversed language: every edge flipped, the old start
+    // as the only accepting state and a new start with epsilon edges to the
+    // old accepting states. Anchors swap sides.
+    pub fn reverse(&self) -> NFAGraph {
+        let mut graph = NFAGraph::empty();
+        graph.valid = self.valid;
+        graph.active_state_limit = self.active_state_limit;
+        graph.dot_matches_newline = self.dot_matches_newline;
+        graph.line_terminator = self.line_terminator;
+        for id in self.states.keys() {
+            graph.states.insert(*id, State::new(*id));
+        }
+        for state in self.states.values() {
+            for (target, transition) in state.outs.iter() {
+                let transition = match transition {
+                    Transition::Assert(assertion) => Transition::Assert(assertion.reversed()),
+                    t => t.clone(),
+                };
+                let target = graph.states.get_mut(target).unwrap();
+                target.outs.insert(state.id, transition);
+            }
+        }
+        let mut start = State::new(StateId(self.last_id));
+        for end in self.ends.iter() {
+            start.outs.insert(*end, Transition::Epsilon);
+        }
+        graph.start = start.id;
+        graph.states.insert(start.id, start);
+        graph.last_id = self.last_id + 1;
+        graph.ends = vec![self.start];
+        graph
+    }
+
+    // The literal chars on the path from the start state up to the first
+    // state with a choice, an assertion or a wildcard. Every match consumes
+    // exactly these first. Also tells whether the chain is the whole graph,
+    // ending in the only accepting state.
+    fn literal_prefix(&self) -> (String, bool) {
+        let mut prefix = String::new();
+        let mut state_id = self.start;
+        // single-edge chains can't loop, but don't trust that blindly
+        for _ in 0..self.states.len() {
+            if self.is_accepting(&state_id) {
+                let whole = self.ends.len() == 1 && self.states[&state_id].outs.is_empty();
+                return (prefix, whole);
+            }
+            let state = self.states.get(&state_id).unwrap();
+            if state.outs.len() != 1 {
+                break;
+            }
+            let (next, transition) = state.outs.iter().next().unwrap();
+            match transition {
+                Transition::Char(chars) if chars.len() == 1 => prefix.push(chars[0]),
+                t if t.is_epsilon() => {}
+                _ => break,
+            }
+            state_id = *next;
+        }
+        (prefix, false)
+    }
+
+    // All non-overlapping matches from left to right. After an empty match
+    // the search resumes one char later so it always makes progress; with
+    // `allow_empty` off, empty matches are skipped instead of reported.
+    pub fn find_iter<'r, 't>(&'r self, s: &'t str) -> Matches<'r, 't> {
+        Matches {
+            graph: self,
+            text: s,
+            pos: 0,
+        }
+    }
+
+    // Every match, overlapping ones included: for each start position, one
+    // span per end offset where the pattern accepts. Ordered by start, then
+    // by end.
+    pub fn find_overlapping(&self, s: &str) -> Vec<Match> {
+        let mut matches = Vec::new();
+        if !self.valid {
+            return matches;
+        }
+        let starts = s
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(s.len()));
+        for start in starts {
+            let mut current = self.start_threads(self.start, Context::at(s, start));
+            if self.accepts(&current) {
+                matches.push(Match { start, end: start });
+            }
+            for (i, c) in s[start..].char_indices() {
+                let end = start + i + c.len_utf8();
+                current = self.step(&current, c, Context::at(s, end));
+                if current.is_empty() {
+                    break;
+                }
+                if self.accepts(&current) {
+                    matches.push(Match { start, end });
+                }
+            }
+        }
+        matches
+    }
+
+    // Number of non-overlapping matches, advancing exactly like `find_iter`.
+    pub fn count_matches(&self, s: &str) -> usize {
+        self.find_iter(s).count()
+    }
+
+    // The lines of `reader` that contain a match, like grep, with their
+    // 1-based line numbers. Lines are read one at a time and handed out
+    // without their `\n` or `\r\n`. Reading stops at the first I/O error.
+    pub fn find_in_reader<'r, R: BufRead + 'r>(
+        &'r self,
+        reader: R,
+    ) -> impl Iterator<Item = (usize, String)> + 'r {
+        reader
+            .lines()
+            .map_while(Result::ok)
+            .enumerate()
+            .filter(|(_, line)| self.find(line).is_some())
+            .map(|(i, line)| (i + 1, line))
+    }
+
+    // Like `find`, but also reports which top-level alternative produced the
+    // match. Patterns without a top-level `|` always report alternative 0.
+    pub fn find_tagged(&self, s: &str) -> Option<(usize, Match)> {
+        let m = self.find(s)?;
+        let index = self
+            .alternatives
+            .iter()
+            .position(|alt| self.accepts_from(*alt, s, m))
+            .unwrap_or(0);
+        Some((index, m))
+    }
+
+    // Whether consuming exactly the span `m` of `s` from `state_id` ends in an
+    // accepting state.
+    fn accepts_from(&self, state_id: StateId, s: &str, m: Match) -> bool {
+        let mut current = self.start_threads(state_id, Context::at(s, m.start));
+        for (i, c) in s[m.start..m.end].char_indices() {
+            let at = m.start + i + c.len_utf8();
+            current = self.step(&current, c, Context::at(s, at));
+        }
+        self.accepts(&current)
+    }
+
+    // Like `str::match_indices`: the non-overlapping matches as offsets
+    // together with the matched slice of `s`.
+    pub fn match_indices<'a>(&self, s: &'a str) -> Vec<(usize, usize, &'a str)> {
+        self.find_iter(s)
+            .map(|m| (m.start, m.end, &s[m.start..m.end]))
+            .collect()
+    }
+
+    pub fn matched_strings(&self, s: &str) -> Vec<String> {
+        self.find_iter(s)
+            .map(|m| s[m.start..m.end].to_string())
+            .collect()
+    }
+
+    // Up to `limit` strings of at most `max_len` chars that the pattern
+    // accepts, shortest first. Listed members of a class are all tried, while
+    // wildcards, negated and Unicode classes contribute one example char
+    // each. Loops are cut off by the length bound.
+    pub fn sample_matches(&self, max_len: usize, limit: usize) -> Vec<String> {
+        let mut samples = Vec::new();
+        if !self.valid || limit == 0 {
+            return samples;
+        }
+        // a string with the states it reached before the closure, which
+        // depends on the char that comes next
+        let mut queue: VecDeque<(String, usize, Threads)> =
+            VecDeque::from([(String::new(), 0, self.threads(self.start))]);
+        while let Some((sample, len, moved)) = queue.pop_front() {
+            let prev = sample.chars().next_back();
+            let end = self.close(moved.clone(), Context { prev, next: None });
+            if self.accepts(&end) {
+                samples.push(sample.clone());
+                if samples.len() == limit {
+                    break;
+                }
+            }
+            if len == max_len {
+                continue;
+            }
+            for c in self.sample_chars(&moved.states()) {
+                let ctx = Context {
+                    prev,
+                    next: Some(c),
+                };
+                let closed = self.close(moved.clone(), ctx);
+                let next = self.advance(&closed, |ids| self.move2(c, ids));
+                if !next.is_empty() {
+                    let mut longer = sample.clone();
+                    longer.push(c);
+                    queue.push_back((longer, len + 1, next));
+                }
+            }
+        }
+        samples
+    }
+
+    // Chars worth trying after reaching `moved`, collected from every edge
+    // reachable without consuming input. Assertions and counters are assumed
+    // to let the thread through, the caller checks them on the way.
+    fn sample_chars(&self, moved: &[StateId]) -> BTreeSet<char> {
+        let mut chars = BTreeSet::new();
+        let mut seen: HashSet<StateId> = moved.iter().copied().collect();
+        let mut stack = moved.to_vec();
+        while let Some(id) = stack.pop() {
+            for (target, transition) in self.states[&id].outs.iter() {
+                match transition {
+                    Transition::Char(members) => chars.extend(members.iter().copied()),
+                    Transition::Epsilon
+                    | Transition::LazyEpsilon
+                    | Transition::Save(_)
+                    | Transition::Assert(_)
+                    | Transition::Counter(_) => {
+                        if seen.insert(*target) {
+                            stack.push(*target);
+                        }
+                    }
+                    // bytes mode edges have no char to offer
+                    Transition::Byte(_) => {}
+                    t => {
+                        // common chars first, then the first one that fits
+                        if let Some(c) = ['a', 'A', '0', ' ', '_']
+                            .into_iter()
+                            .chain((0..0x3_0000).filter_map(char::from_u32))
+                            .find(|c| self.takes(t, *c))
+                        {
+                            chars.insert(c);
+                        }
+                    }
+                }
+            }
+        }
+        chars
+    }
+
+    // End offset of the preferred match starting exactly at `at`: the one
+    // taking the fewest lazy edges, and among those the longest.
+    fn longest_match_at(&self, s: &str, at: usize) -> Option<usize> {
+        self.longest_match_chars(s[at..].chars(), at, s[..at].chars().next_back())
+    }
+
+    // `longest_match_at` for the chars from byte offset `at` on, where
+    // `prev` is the char before them.
+    fn longest_match_chars<I: Iterator<Item = char>>(
+        &self,
+        chars: I,
+        at: usize,
+        prev: Option<char>,
+    ) -> Option<usize> {
+        let mut chars = chars.peekable();
+        let ctx = Context {
+            prev,
+            next: chars.peek().copied(),
+        };
+        let mut current =
+            self.weighted_closure(vec![((self.start, vec![0; self.counters]), 0)], ctx);
+        let mut best = self.accept_cost(&current).map(|cost| (cost, at));
+        let mut end = at;
+        while let Some(c) = chars.next() {
+            if let Some((cost, _)) = best {
+                // threads that are already lazier than the best match can't win
+                current.retain(|_, c| *c <= cost);
+            }
+            end += c.len_utf8();
+            let ctx = Context {
+                prev: Some(c),
+                next: chars.peek().copied(),
+            };
+            current = self.weighted_closure(self.weighted_move(c, &current), ctx);
+            if current.is_empty() {
+                break;
+            }
+            if let Some(cost) = self.accept_cost(&current) {
+                if best.is_none_or(|(best_cost, _)| cost <= best_cost) {
+                    best = Some((cost, end));
+                }
+            }
+        }
+        best.map(|(_, end)| end)
+    }
+
+    fn shortest_match_at(&self, s: &str, at: usize) -> Option<usize> {
+        let mut current = self.start_threads(self.start, Context::at(s, at));
+        if self.accepts(&current) {
+            return Some(at);
+        }
+        for (i, c) in s[at..].char_indices() {
+            let end = at + i + c.len_utf8();
+            current = self.step(&current, c, Context::at(s, end));
+            if current.is_empty() {
+                break;
+            }
+            if self.accepts(&current) {
+                return Some(end);
+            }
+        }
+        None
+    }
+
+    fn accept_cost(&self, current: &HashMap<(StateId, Vec<usize>), usize>) -> Option<usize> {
+        current
+            .iter()
+            .filter(|((id, _), _)| self.is_accepting(id))
+            .map(|(_, cost)| *cost)
+            .min()
+    }
+
+    // Epsilon closure that also tracks, per thread, the fewest lazy edges
+    // needed to reach it. A thread is a state with the counters of the
+    // counted loops, none for a graph without them.
+    fn weighted_closure(
+        &self,
+        seeds: Vec<((StateId, Vec<usize>), usize)>,
+        ctx: Context,
+    ) -> HashMap<(StateId, Vec<usize>), usize> {
+        let mut costs: HashMap<(StateId, Vec<usize>), usize> = HashMap::new();
+        let mut queue: VecDeque<((StateId, Vec<usize>), usize)> = seeds.into_iter().collect();
+        while let Some(((state_id, counters), cost)) = queue.pop_front() {
+            let thread = (state_id, counters);
+            if costs.get(&thread).is_some_and(|c| *c <= cost) {
+                continue;
+            }
+            let state = self.states.get(&state_id).unwrap();
+            for out in state.outs.iter() {
+                let counters = &thread.1;
+                match out.1 {
+                    Transition::Epsilon | Transition::Save(_) => {
+                        queue.push_front(((*out.0, counters.clone()), cost))
+                    }
+                    Transition::LazyEpsilon => {
+                        queue.push_back(((*out.0, counters.clone()), cost + 1))
+                    }
+                    Transition::Assert(assertion) if assertion.holds(ctx) => {
+                        queue.push_front(((*out.0, counters.clone()), cost))
+                    }
+                    Transition::Counter(op) => {
+                        if let Some(counters) = op.apply(counters) {
+                            queue.push_front(((*out.0, counters), cost))
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            costs.insert(thread, cost);
+        }
+        costs
+    }
+
+    fn weighted_move(
+        &self,
+        c: char,
+        current: &HashMap<(StateId, Vec<usize>), usize>,
+    ) -> Vec<((StateId, Vec<usize>), usize)> {
+        let mut next = Vec::new();
+        for ((state_id, counters), cost) in current.iter() {
+            for target in self.move2(c, &[*state_id]) {
+                next.push(((target, counters.clone()), *cost));
+            }
+        }
+        next
+    }
+
+    pub fn active_states(&self, s: &str, up_to: usize) -> Vec<StateId> {
+        let mut active = self.start_threads(self.start, Context::at(s, 0));
+        for (i, c) in s.char_indices().take(up_to) {
+            let ctx = Context::at(s, i + c.len_utf8());
+            active = self.step(&active, c, ctx);
+        }
+        active.states()
+    }
+
+    // Sizes of the graph, e.g. to see what `trim` or `coalesce_epsilons`
+    // saved or to turn down a pathological pattern before matching.
+    pub fn stats(&self) -> GraphStats {
+        let edges = || self.states.values().flat_map(|state| state.outs.values());
+        GraphStats {
+            state_count: self.states.len(),
+            transition_count: edges().count(),
+            epsilon_count: edges().filter(|t| t.is_epsilon()).count(),
+            char_edge_count: edges()
+                .filter(|t| {
+                    !t.is_epsilon() && !matches!(t, Transition::Assert(_) | Transition::Counter(_))
+                })
+                .count(),
+        }
+    }
+
+    // Every transition as a (from, to, transition) triple of plain state
+    // ids, ordered by the ids, for handing the graph to tools that don't
+    // know about `StateId`.
+    pub fn edges(&self) -> Vec<(usize, usize, Transition)> {
+        let mut edges: Vec<_> = self
+            .states
+            .iter()
+            .flat_map(|(from, state)| {
+                state
+                    .outs
+                    .iter()
+                    .map(|(to, transition)| (from.0, to.0, transition.clone()))
+            })
+            .collect();
+        edges.sort_unstable_by_key(|(from, to, _)| (*from, *to));
+        edges
+    }
+
+    pub fn start_id(&self) -> usize {
+        self.start.0
+    }
+
+    // The ids of the accepting states, in ascending order.
+    pub fn end_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.ends.iter().map(|id| id.0).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // Every char on some char edge of the graph. A wildcard can consume
+    // chars outside of it, see `has_wildcard`.
+    pub fn alphabet(&self) -> BTreeSet<char> {
+        let mut alphabet = BTreeSet::new();
+        for state in self.states.values() {
+            for transition in state.outs.values() {
+                if let Transition::Char(chars) = transition {
+                    alphabet.extend(chars.iter().copied());
+                }
+            }
+        }
+        alphabet
+    }
+
+    // Whether the alphabet is open, i.e. some edge takes chars that aren't
+    // listed anywhere: a wildcard, a negated class or a Unicode class.
+    pub fn has_wildcard(&self) -> bool {
+        self.states.values().any(|state| {
+            state.outs.values().any(|t| {
+                matches!(
+                    t,
+                    Transition::Any
+                        | Transition::NotChar(_)
+                        | Transition::Class(_)
+                        | Transition::NotClass(..)
+                )
+            })
+        })
+    }
+
+    // For debugging: the active states, sorted by id, after each consumed
+    // char. The trace stops early once no state is left.
+    pub fn trace(&self, s: &str) -> Vec<(char, Vec<StateId>)> {
+        let mut steps = Vec::new();
+        let mut active = self.start_threads(self.start, Context::at(s, 0));
+        for (i, c) in s.char_indices() {
+            let ctx = Context::at(s, i + c.len_utf8());
+            active = self.step(&active, c, ctx);
+            let mut sorted = active.states();
+            sorted.sort();
+            steps.push((c, sorted));
+            if active.is_empty() {
+                break;
+            }
+        }
+        steps
+    }
+
+    // For debugging: why `s` doesn't match the pattern as a whole, i.e. where
+    // the last active states ran out and what they would have taken there.
+    pub fn explain(&self, s: &str) -> MatchExplanation {
+        let mut explanation = MatchExplanation {
+            matched: false,
+            position: None,
+            expected: BTreeSet::new(),
+            open: false,
+        };
+        if !self.valid {
+            return explanation;
+        }
+        let mut active = self.start_threads(self.start, Context::at(s, 0));
+        let mut position = s.len();
+        for (i, c) in s.char_indices() {
+            let next = self.step(&active, c, Context::at(s, i + c.len_utf8()));
+            if next.is_empty() {
+                position = i;
+                break;
+            }
+            active = next;
+        }
+        if position == s.len() && self.accepts(&active) {
+            explanation.matched = true;
+            return explanation;
+        }
+        explanation.position = Some(position);
+        let active = active.states();
+        for transition in active.iter().flat_map(|id| self.states[id].outs.values()) {
+            match transition {
+                Transition::Char(chars) => explanation.expected.extend(chars.iter().copied()),
+                Transition::Any
+                | Transition::NotChar(_)
+                | Transition::Class(_)
+                | Transition::NotClass(..) => explanation.open = true,
+                _ => {}
+            }
+        }
+        explanation
+    }
+
+    pub(crate) fn is_accepting(&self, state_id: &StateId) -> bool {
+        self.ends.contains(state_id)
+    }
+
+    pub(crate) fn closure(&self, current_set: Vec<StateId>, ctx: Context) -> Vec<StateId> {
+        let mut seen: HashSet<StateId> = HashSet::with_capacity(current_set.len());
+        let mut closure_set = Vec::with_capacity(current_set.len());
+        let mut queue = VecDeque::new();
+        for cl in current_set {
+            if seen.insert(cl) {
+                closure_set.push(cl);
+                queue.push_back(cl);
+            }
+        }
+        while !queue.is_empty() {
+            let state_id = queue.pop_front().unwrap();
+            let state = self.states.get(&state_id).unwrap();
+            for out in state.outs.iter() {
+                let follow = match out.1 {
+                    Transition::Assert(assertion) => assertion.holds(ctx),
+                    t => t.is_epsilon(),
+                };
+                if follow && seen.insert(*out.0) {
+                    closure_set.push(*out.0);
+                    queue.push_back(*out.0);
+                }
+            }
+        }
+        closure_set
+    }
+
+    // Targets of the edges consuming `c`, each listed once even when several
+    // states lead there.
+    pub(crate) fn move2(&self, c: char, current_set: &[StateId]) -> Vec<StateId> {
+        let mut seen = HashSet::new();
+        let mut next_set = Vec::new();
+        for state_id in current_set.iter() {
+            let state = self.states.get(state_id).unwrap();
+            let by_char = self.char_index.get(state_id);
+            if let Some(targets) = by_char.and_then(|by_char| by_char.get(&c)) {
+                for target in targets {
+                    if seen.insert(*target) {
+                        next_set.push(*target);
+                    }
+                }
+            }
+            for out in state.outs.iter() {
+                // indexed `Char` edges were handled above
+                if by_char.is_some() && matches!(out.1, Transition::Char(_)) {
+                    continue;
+                }
+                if self.takes(out.1, c) && seen.insert(*out.0) {
+                    next_set.push(*out.0);
+                }
+            }
+        }
+        next_set
+    }
+
+    // A new thread in `id` with every counter at zero, before its closure.
+    pub(crate) fn threads(&self, id: StateId) -> Threads {
+        if self.counters == 0 {
+            Threads::States(vec![id])
+        } else {
+            Threads::Counted(vec![(id, vec![0; self.counters])])
+        }
+    }
+
+    // The closure of a new thread in `id`.
+    pub(crate) fn start_threads(&self, id: StateId, ctx: Context) -> Threads {
+        self.close(self.threads(id), ctx)
+    }
+
+    // `closure` for threads, following the counter edges their counters
+    // allow.
+    pub(crate) fn close(&self, threads: Threads, ctx: Context) -> Threads {
+        match threads {
+            Threads::States(ids) => Threads::States(self.closure(ids, ctx)),
+            Threads::Counted(threads) => Threads::Counted(self.counted_closure(threads, ctx)),
+        }
+    }
+
+    // Moves every thread along the edges `moves` finds from its state, like
+    // `move2` does for a char, keeping its counters.
+    pub(crate) fn advance(
+        &self,
+        threads: &Threads,
+        moves: impl Fn(&[StateId]) -> Vec<StateId>,
+    ) -> Threads {
+        match threads {
+            Threads::States(ids) => Threads::States(moves(ids)),
+            Threads::Counted(threads) => Threads::Counted(
+                threads
+                    .iter()
+                    .flat_map(|(id, counters)| {
+                        moves(&[*id])
+                            .into_iter()
+                            .map(move |target| (target, counters.clone()))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    // The threads after consuming `c`, closed in `ctx`.
+    pub(crate) fn step(&self, threads: &Threads, c: char, ctx: Context) -> Threads {
+        self.close(self.advance(threads, |ids| self.move2(c, ids)), ctx)
+    }
+
+    pub(crate) fn accepts(&self, threads: &Threads) -> bool {
+        match threads {
+            Threads::States(ids) => ids.iter().any(|id| self.is_accepting(id)),
+            Threads::Counted(threads) => threads.iter().any(|(id, _)| self.is_accepting(id)),
+        }
+    }
+
+    // `closure` over threads with counters.
+    fn counted_closure(
+        &self,
+        threads: Vec<(StateId, Vec<usize>)>,
+        ctx: Context,
+    ) -> Vec<(StateId, Vec<usize>)> {
+        let mut seen: HashSet<(StateId, Vec<usize>)> = HashSet::new();
+        let mut closure_set = Vec::new();
+        let mut stack = Vec::new();
+        for thread in threads {
+            if seen.insert(thread.clone()) {
+                closure_set.push(thread.clone());
+                stack.push(thread);
+            }
+        }
+        while let Some((id, counters)) = stack.pop() {
+            for (target, transition) in self.states[&id].outs.iter() {
+                let next = match transition {
+                    Transition::Assert(assertion) if assertion.holds(ctx) => Some(counters.clone()),
+                    Transition::Counter(op) => op.apply(&counters),
+                    t if t.is_epsilon() => Some(counters.clone()),
+                    _ => None,
+                };
+                if let Some(counters) = next {
+                    let thread = (*target, counters);
+                    if seen.insert(thread.clone()) {
+                        closure_set.push(thread.clone());
+                        stack.push(thread);
+                    }
+                }
+            }
+        }
+        closure_set
+    }
+
+    // Whether the edge `transition` consumes `c`.
+    pub(crate) fn takes(&self, transition: &Transition, c: char) -> bool {
+        match transition {
+            Transition::Any => !self.line_terminator.contains(c) || self.dot_matches_newline,
+            t => t.takes_char(c),
+        }
+    }
+
+    fn move_byte(&self, b: u8, current_set: &[StateId]) -> Vec<StateId> {
+        let mut next_set = Vec::new();
+        for state_id in current_set.iter() {
+            let state = self.states.get(state_id).unwrap();
+            for out in state.outs.iter() {
+                match out.1 {
+                    Transition::Byte(bytes) if bytes.contains(&b) => next_set.push(*out.0),
+                    Transition::Any
+                        if !self.line_terminator.contains(b as char)
+                            || self.dot_matches_newline =>
+                    {
+                        next_set.push(*out.0)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        next_set
+    }
+
+    pub fn display(&self) {
+        print!("{}", self.dump());
+    }
+
+    // One line per state in id order with its edges in target order, so the
+    // output of two runs can be diffed.
+    pub fn dump(&self) -> String {
+        let mut ids: Vec<&StateId> = self.states.keys().collect();
+        ids.sort();
+        let mut dump = String::new();
+        for id in ids {
+            let mut outs: Vec<_> = self.states[id].outs.iter().collect();
+            outs.sort_by_key(|(target, _)| **target);
+            let outs: Vec<String> = outs
+                .iter()
+                .map(|(target, transition)| format!("{}: {}", target.0, transition))
+                .collect();
+            dump.push_str(&format!(
+                "state id: {}, state outs: {{{}}}\n",
+                id.0,
+                outs.join(", ")
+            ));
+        }
+        dump
+    }
+
+    // Graphviz rendering, accepting states drawn with a double circle.
+    pub fn to_dot(&self) -> String {
+        let mut ids: Vec<&StateId> = self.states.keys().collect();
+        ids.sort();
+        let mut dot = String::from("digraph nfa {\n    rankdir=LR;\n");
+        for id in ids.iter() {
+            let shape = if self.is_accepting(id) {
+                "doublecircle"
+            } else {
+                "circle"
+            };
+            dot.push_str(&format!("    {} [shape={}];\n", id.0, shape));
+        }
+        dot.push_str(&format!(
+            "    start [shape=point];\n    start -> {};\n",
+            self.start.0
+        ));
+        for id in ids {
+            let mut outs: Vec<_> = self.states[id].outs.iter().collect();
+            outs.sort_by_key(|(target, _)| **target);
+            for (target, transition) in outs {
+                let label = transition
+                    .to_string()
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                dot.push_str(&format!(
+                    "    {} -> {} [label=\"{}\"];\n",
+                    id.0, target.0, label
+                ));
+            }
+        }
+        dot.push('}');
+        dot
+    }
+}
+
+// Splits postfix as produced by `re2post` into tokens. Operators are the
+// bare `.`, `|`, `?`, `*`, `+`, `!`, `(n)` and `{min,max}`, a `~` after a
+// quantifier makes it lazy, and a literal operator char is escaped with `\`.
+pub fn postfix_tokens(post: &str) -> Result<Vec<PostfixToken>, RegexError> {
+    let mut tokens = Vec::new();
+    let mut chars = post.chars().peekable();
+    while let Some(post_char) = chars.next() {
+        let token = match post_char {
+            '.' => PostfixToken::Concat,
+            '|' => PostfixToken::Alternate,
+            '!' => PostfixToken::NotBefore,
+            '?' | '*' | '+' => {
+                let lazy = chars.next_if_eq(&'~').is_some();
+                match post_char {
+                    '?' => PostfixToken::Optional(lazy),
+                    '*' => PostfixToken::Star(lazy),
+                    _ => PostfixToken::Plus(lazy),
+                }
+            }
+            '_' => PostfixToken::Edge(Transition::Any),
+            '\\' => match chars.next() {
+                Some('b') => PostfixToken::Edge(Transition::Assert(Assertion::WordBoundary)),
+                Some('B') => PostfixToken::Edge(Transition::Assert(Assertion::NotWordBoundary)),
+                Some('A') => PostfixToken::Edge(Transition::Assert(Assertion::StartText)),
+                Some('z') => PostfixToken::Edge(Transition::Assert(Assertion::EndText)),
+                // the empty branch of an alternation
+                Some('e') => PostfixToken::Edge(Transition::Epsilon),
+                Some('d') => PostfixToken::Edge(Transition::Char(('0'..='9').collect())),
+                Some(kind @ ('p' | 'P')) => {
+                    if chars.next() != Some('{') {
+                        return Err(RegexError::MalformedPostfix);
+                    }
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(RegexError::MalformedPostfix),
+                        }
+                    }
+                    let id = ClassId::from_name(&name).ok_or(RegexError::MalformedPostfix)?;
+                    PostfixToken::Class(id, kind == 'P')
+                }
+                // any other escaped char is a literal
+                Some(c) if !c.is_alphanumeric() => PostfixToken::Literal(c),
+                _ => return Err(RegexError::MalformedPostfix),
+            },
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut members = Vec::new();
+                let mut classes = Vec::new();
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        // a Unicode class among the members of a negated set
+                        Some('\\') if negated && chars.next_if_eq(&'p').is_some() => {
+                            if chars.next_if_eq(&'{').is_none() {
+                                members.push('p');
+                                continue;
+                            }
+                            let mut name = String::new();
+                            loop {
+                                match chars.next() {
+                                    Some('}') => break,
+                                    Some(c) => name.push(c),
+                                    None => return Err(RegexError::MalformedPostfix),
+                                }
+                            }
+                            classes.push(
+                                ClassId::from_name(&name).ok_or(RegexError::MalformedPostfix)?,
+                            );
+                        }
+                        Some('\\') => match chars.next() {
+                            Some(c) => members.push(c),
+                            None => return Err(RegexError::MalformedPostfix),
+                        },
+                        Some(c) => members.push(c),
+                        None => return Err(RegexError::MalformedPostfix),
+                    }
+                }
+                if classes.is_empty() {
+                    PostfixToken::Set(members, negated)
+                } else {
+                    PostfixToken::Edge(Transition::NotClass(classes, members))
+                }
+            }
+            '(' => {
+                // `(n)` wraps the fragment on the stack in capture group n
+                let mut group = 0usize;
+                loop {
+                    match chars.next() {
+                        Some(')') => break,
+                        Some(c) if c.is_ascii_digit() => {
+                            group = group
+                                .checked_mul(10)
+                                .and_then(|g| g.checked_add(c as usize - '0' as usize))
+                                .filter(|g| *g < usize::MAX / 2)
+                                .ok_or(RegexError::MalformedPostfix)?;
+                        }
+                        _ => return Err(RegexError::MalformedPostfix),
+                    }
+                }
+                PostfixToken::Group(group)
+            }
+            '{' => {
+                // `{min,max}`, or `{min,}` without an upper bound
+                let number = |chars: &mut Peekable<Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits.parse::<usize>().ok()
+                };
+                let min = number(&mut chars).ok_or(RegexError::MalformedPostfix)?;
+                if chars.next() != Some(',') {
+                    return Err(RegexError::MalformedPostfix);
+                }
+                let max = number(&mut chars);
+                if chars.next() != Some('}') || max.is_some_and(|max| max < min) {
+                    return Err(RegexError::MalformedPostfix);
+                }
+                PostfixToken::Repeat(min, max)
+            }
+            '^' => PostfixToken::Start,
+            '$' => PostfixToken::End,
+            c if c.is_alphanumeric() => PostfixToken::Literal(c),
+            _ => return Err(RegexError::MalformedPostfix),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// Replaces every counted repetition by copies of its operand: `X{2,4}`
+// becomes `XX(X(X)?)?` and `X{2,}` becomes `XX+`. Copies of a group all
+// save to the slots of that group, so it reports its last iteration as
+// usual. The copies count against `size_limit` before any states are
+// built, so a huge count fails early.
+fn expand_repeats(
+    tokens: Vec<PostfixToken>,
+    size_limit: usize,
+) -> Result<Vec<PostfixToken>, RegexError> {
+    if !tokens.iter().any(|t| matches!(t, PostfixToken::Repeat(..))) {
+        return Ok(tokens);
+    }
+    let mut out: Vec<PostfixToken> = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let PostfixToken::Repeat(min, max) = token else {
+            out.push(token);
+            continue;
+        };
+        // the operand is the shortest suffix of `out` that forms one
+        // fragment, already expanded itself
+        let mut needed = 1usize;
+        let mut at = out.len();
+        while needed > 0 {
+            at = at.checked_sub(1).ok_or(RegexError::MalformedPostfix)?;
+            needed = needed - 1 + out[at].operands();
+        }
+        let operand = out.split_off(at);
+        // counts too large to even add up are over any limit
+        let size = min
+            .checked_add(max.map_or(1, |max| max.saturating_sub(min)))
+            .and_then(|copies| operand.len().checked_mul(copies));
+        if size.is_none_or(|size| size > size_limit) {
+            return Err(RegexError::SizeLimitExceeded(size_limit));
+        }
+        let start = out.len();
+        for i in 0..min {
+            out.extend(operand.iter().cloned());
+            // the last required copy repeats when there's no upper bound
+            if max.is_none() && i + 1 == min {
+                out.push(PostfixToken::Plus(false));
+            }
+            if i > 0 {
+                out.push(PostfixToken::Concat);
+            }
+        }
+        match max {
+            None if min == 0 => {
+                out.extend(operand.iter().cloned());
+                out.push(PostfixToken::Star(false));
+            }
+            None => {}
+            Some(max) if max > min => {
+                // nested from the inside out: X?, then (X X?)?, ...
+                let mut tail = operand.clone();
+                tail.push(PostfixToken::Optional(false));
+                for _ in 1..max - min {
+                    let mut outer = operand.clone();
+                    outer.append(&mut tail);
+                    outer.push(PostfixToken::Concat);
+                    outer.push(PostfixToken::Optional(false));
+                    tail = outer;
+                }
+                let joined = out.len() > start;
+                out.append(&mut tail);
+                if joined {
+                    out.push(PostfixToken::Concat);
+                }
+            }
+            Some(_) if min == 0 => out.push(PostfixToken::Edge(Transition::Epsilon)),
+            Some(_) => {}
+        }
+    }
+    Ok(out)
+}
+
+// Replaces every alternation of two or more plain literal words, like
+// `cat|dog|cow`, by a single `Words` token, so the words share the states
+// of their common prefixes instead of each getting a branch of its own.
+// Malformed postfix is returned unchanged for the compiler to report.
+fn factor_words(tokens: Vec<PostfixToken>) -> Vec<PostfixToken> {
+    // the tokens of an operand on the stack, and its words while it only
+    // matches literal words
+    struct Operand {
+        tokens: Vec<PostfixToken>,
+        words: Option<Vec<String>>,
+    }
+    impl Operand {
+        // the tokens to use once the operand takes part in something that
+        // isn't a word list itself
+        fn emit(self) -> Vec<PostfixToken> {
+            match self.words {
+                Some(words) if words.len() > 1 => vec![PostfixToken::Words(words)],
+                _ => self.tokens,
+            }
+        }
+    }
+    let mut stack: Vec<Operand> = Vec::new();
+    for token in tokens.iter() {
+        let operands = token.operands();
+        if stack.len() < operands {
+            return tokens;
+        }
+        let mut args = stack.split_off(stack.len() - operands);
+        let words = match (token, args.as_mut_slice()) {
+            (PostfixToken::Literal(c), []) => Some(vec![c.to_string()]),
+            (PostfixToken::Concat, [a, b]) => match (&a.words, &b.words) {
+                (Some(a), Some(b)) if a.len() == 1 && b.len() == 1 => {
+                    Some(vec![format!("{}{}", a[0], b[0])])
+                }
+                _ => None,
+            },
+            (PostfixToken::Alternate, [a, b]) => match (a.words.take(), b.words.take()) {
+                (Some(mut a), Some(b)) => {
+                    a.extend(b);
+                    Some(a)
+                }
+                (a_words, b_words) => {
+                    a.words = a_words;
+                    b.words = b_words;
+                    None
+                }
+            },
+            _ => None,
+        };
+        let mut operand = Operand {
+            tokens: Vec::new(),
+            words,
+        };
+        // a word list only ever grows, so once it has several words its
+        // tokens are never needed again
+        if operand.words.as_ref().is_none_or(|words| words.len() == 1) {
+            for arg in args {
+                if operand.words.is_some() {
+                    operand.tokens.extend(arg.tokens);
+                } else {
+                    operand.tokens.extend(arg.emit());
+                }
+            }
+            operand.tokens.push(token.clone());
+        }
+        stack.push(operand);
+    }
+    if stack.len() > 1 {
+        return tokens;
+    }
+    stack.pop().map(Operand::emit).unwrap_or_default()
+}
+
+// Checks that every operator in `tokens` has its operands and that they
+// reduce to at most one fragment, without building any states.
+pub(crate) fn check_arity(tokens: &[PostfixToken]) -> Result<(), RegexError> {
+    let mut depth = 0usize;
+    for token in tokens {
+        let operands = token.operands();
+        depth = depth
+            .checked_sub(operands)
+            .ok_or(RegexError::MalformedPostfix)?
+            + 1;
+    }
+    if depth > 1 {
+        return Err(RegexError::MalformedPostfix);
+    }
+    Ok(())
+}
+
+// The operand of a postfix operator, which has to be on the stack.
+fn pop(stack: &mut Vec<Frag>) -> Result<Frag, RegexError> {
+    stack.pop().ok_or(RegexError::MalformedPostfix)
+}
+
+// A literal char as a postfix atom. Alphanumerics stand for themselves,
+// anything else is escaped so it can't be mistaken for an operator.
+fn postfix_literal(c: char) -> String {
+    if c.is_alphanumeric() {
+        c.to_string()
+    } else {
+        format!("\\{}", c)
+    }
+}
+
+// Decodes the code point of a `\xHH` or `\u{...}` escape. `kind` is the
+// `x` or `u`, which has already been consumed.
+fn parse_code_point(kind: char, chars: &mut Peekable<Chars>) -> Option<char> {
+    if kind == 'x' {
+        let hi = chars.next()?.to_digit(16)?;
+        let lo = chars.next()?.to_digit(16)?;
+        return char::from_u32(hi * 16 + lo);
+    }
+    if chars.next()? != '{' {
+        return None;
+    }
+    let mut value = 0u32;
+    let mut digits = 0;
+    loop {
+        match chars.next()? {
+            '}' => break,
+            c => value = value * 16 + c.to_digit(16)?,
+        }
+        digits += 1;
+        if digits > 6 {
+            return None;
+        }
+    }
+    if digits == 0 {
+        return None;
+    }
+    // rejects surrogates and anything past U+10FFFF
+    char::from_u32(value)
+}
+
+// Members of the POSIX classes allowed inside brackets, ASCII only.
+fn posix_class(name: &str) -> Option<&'static [(char, char)]> {
+    let ranges: &[(char, char)] = match name {
+        "alpha" => &[('A', 'Z'), ('a', 'z')],
+        "digit" => &[('0', '9')],
+        "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+        // \t, \n, \v, \f, \r and the space
+        "space" => &[('\t', '\r'), (' ', ' ')],
+        "upper" => &[('A', 'Z')],
+        "lower" => &[('a', 'z')],
+        _ => return None,
+    };
+    Some(ranges)
+}
+
+// The Unicode classes a POSIX class maps to outside ASCII mode.
+fn unicode_posix_class(name: &str) -> Option<&'static [ClassId]> {
+    let ids: &[ClassId] = match name {
+        "alpha" => &[ClassId::Letter],
+        "digit" => &[ClassId::Number],
+        "alnum" => &[ClassId::Letter, ClassId::Number],
+        "space" => &[ClassId::Whitespace],
+        "upper" => &[ClassId::Uppercase],
+        "lower" => &[ClassId::Lowercase],
+        _ => return None,
+    };
+    Some(ids)
+}
+
+// Classes are expanded into their members, so keep them to a sane size.
+const MAX_CLASS_LEN: u64 = 1 << 16;
+
+// A single char inside brackets: a literal, or an escaped literal.
+fn class_member(c: char, chars: &mut Peekable<Chars>) -> Option<char> {
+    if c != '\\' {
+        return Some(c);
+    }
+    match chars.next()? {
+        kind @ ('x' | 'u') => parse_code_point(kind, chars),
+        c if !c.is_alphanumeric() => Some(c),
+        _ => None,
+    }
+}
+
+// Parses a bracket class, right after its `[`. Outside ASCII mode `\d`,
+// `\s`, `\w` and the POSIX classes are Unicode classes.
+fn parse_class(chars: &mut Peekable<Chars>, ascii: bool, fold: bool) -> Option<CharClass> {
+    let negated = chars.next_if_eq(&'^').is_some();
+    let mut ranges: Vec<(char, char)> = Vec::new();
+    let mut classes: Vec<ClassId> = Vec::new();
+    // a `]` right after the opening bracket is a member
+    let mut first = true;
+    loop {
+        let c = chars.next()?;
+        if c == ']' && !first {
+            break;
+        }
+        first = false;
+        if c == '[' && chars.next_if_eq(&':').is_some() {
+            let mut name = String::new();
+            loop {
+                match chars.next()? {
+                    ':' => break,
+                    c => name.push(c),
+                }
+            }
+            if chars.next()? != ']' {
+                return None;
+            }
+            if ascii {
+                ranges.extend_from_slice(posix_class(&name)?);
+            } else {
+                classes.extend_from_slice(unicode_posix_class(&name)?);
+            }
+            continue;
+        }
+        if c == '\\' {
+            if let Some(kind) = chars.next_if(|c| matches!(c, 'd' | 's' | 'w')) {
+                match (kind, ascii) {
+                    ('d', true) => ranges.push(('0', '9')),
+                    ('d', false) => classes.push(ClassId::Number),
+                    ('s', true) => ranges.extend_from_slice(posix_class("space")?),
+                    ('s', false) => classes.push(ClassId::Whitespace),
+                    (_, true) => {
+                        ranges.extend_from_slice(posix_class("alnum")?);
+                        ranges.push(('_', '_'));
+                    }
+                    _ => {
+                        classes.extend_from_slice(&[ClassId::Letter, ClassId::Number]);
+                        ranges.push(('_', '_'));
+                    }
+                }
+                continue;
+            }
+        }
+        let lo = class_member(c, chars)?;
+        // `-` between two members makes a range, first or last it's a member
+        let mut ahead = chars.clone();
+        let hi = if ahead.next() == Some('-') && !matches!(ahead.peek(), Some(']') | None) {
+            chars.next();
+            let c = chars.next()?;
+            class_member(c, chars)?
+        } else {
+            lo
+        };
+        if hi < lo {
+            return None;
+        }
+        ranges.push((lo, hi));
+    }
+    // summed wide enough that thousands of full ranges can't wrap around
+    let len: u64 = ranges
+        .iter()
+        .map(|(lo, hi)| u64::from(*hi as u32 - *lo as u32) + 1)
+        .sum();
+    if len > MAX_CLASS_LEN {
+        return None;
+    }
+    let mut members: Vec<char> = ranges.into_iter().flat_map(|(lo, hi)| lo..=hi).collect();
+    if fold {
+        members = members
+            .into_iter()
+            .flat_map(|c| case_variants(c, ascii))
+            .collect();
+    }
+    members.sort_unstable();
+    members.dedup();
+    classes.sort_by_key(|id| id.name());
+    classes.dedup();
+    Some(CharClass {
+        members,
+        classes,
+        negated,
+    })
+}
+
+// Pairs of chars that fold to the same char under Unicode simple case
+// folding but that `to_lowercase` and `to_uppercase` don't lead from the
+// first to the second, like the final sigma or the Kelvin sign.
+#[cfg(feature = "unicode")]
+const FOLD_EXTRA: &[(char, char)] = &[
+    ('k', '\u{212a}'),
+    ('s', '\u{17f}'),
+    ('\u{b5}', '\u{3bc}'),
+    ('\u{df}', '\u{1e9e}'),
+    ('\u{e5}', '\u{212b}'),
+    ('\u{3b2}', '\u{3d0}'),
+    ('\u{3b5}', '\u{3f5}'),
+    ('\u{3b8}', '\u{3d1}'),
+    ('\u{3b8}', '\u{3f4}'),
+    ('\u{3b9}', '\u{345}'),
+    ('\u{3b9}', '\u{1fbe}'),
+    ('\u{3ba}', '\u{3f0}'),
+    ('\u{3c0}', '\u{3d6}'),
+    ('\u{3c1}', '\u{3f1}'),
+    ('\u{3c3}', '\u{3c2}'),
+    ('\u{3c6}', '\u{3d5}'),
+    ('\u{3c9}', '\u{2126}'),
+    ('\u{1e61}', '\u{1e9b}'),
+];
+
+// `c` and every char it's equal to ignoring case, for `(?i:...)`. ASCII
+// mode only folds ASCII letters.
+fn case_variants(c: char, ascii: bool) -> Vec<char> {
+    if ascii {
+        return match c {
+            'a'..='z' => vec![c, c.to_ascii_uppercase()],
+            'A'..='Z' => vec![c, c.to_ascii_lowercase()],
+            _ => vec![c],
+        };
+    }
+    unicode_case_variants(c)
+}
+
+// `c` and every char it's equal to under Unicode simple case folding. Only
+// single-char mappings count, so `ß` doesn't match `SS`. The dotless `ı`
+// folds to itself although its upper case is `I`.
+#[cfg(feature = "unicode")]
+fn unicode_case_variants(c: char) -> Vec<char> {
+    let mut variants = vec![c];
+    if c == '\u{131}' {
+        return variants;
+    }
+    let mut i = 0;
+    while i < variants.len() {
+        let c = variants[i];
+        let lower: Vec<char> = c.to_lowercase().collect();
+        let upper: Vec<char> = c.to_uppercase().collect();
+        let extra = FOLD_EXTRA.iter().filter_map(|&(a, b)| match c {
+            c if c == a => Some(vec![b]),
+            c if c == b => Some(vec![a]),
+            _ => None,
+        });
+        for mapped in [lower, upper].into_iter().chain(extra) {
+            if mapped.len() == 1 && !variants.contains(&mapped[0]) {
+                variants.push(mapped[0]);
+            }
+        }
+        i += 1;
+    }
+    variants
+}
+
+// Without the `unicode` feature, `c` and its single-char upper and lower
+// case forms.
+#[cfg(not(feature = "unicode"))]
+fn unicode_case_variants(c: char) -> Vec<char> {
+    let mut variants = vec![c];
+    let lower: Vec<char> = c.to_lowercase().collect();
+    let upper: Vec<char> = c.to_uppercase().collect();
+    for mapped in [lower, upper] {
+        if mapped.len() == 1 && !variants.contains(&mapped[0]) {
+            variants.push(mapped[0]);
+        }
+    }
+    variants
+}
+
+// The postfix atom for the literal `c`, a class of its case variants when
+// folding case.
+fn literal_atom(c: char, fold: bool, ascii: bool) -> String {
+    let mut variants = if fold {
+        case_variants(c, ascii)
+    } else {
+        vec![c]
+    };
+    if variants.len() == 1 {
+        return postfix_literal(c);
+    }
+    variants.sort_unstable();
+    let mut atom = String::from("[");
+    for c in variants {
+        if matches!(c, '\\' | ']' | '^') {
+            atom.push('\\');
+        }
+        atom.push(c);
+    }
+    atom.push(']');
+    atom
+}
+
+// The postfix atom for a `\d`, `\s` or `\w` shorthand.
+fn shorthand_class(c: char, ascii: bool) -> &'static str {
+    match (c, ascii) {
+        ('d', true) => "\\d",
+        ('d', false) => "\\p{N}",
+        // \t, \n, \v, \f, \r and the space
+        ('s', true) => "[\t\n\u{b}\u{c}\r ]",
+        ('s', false) => "\\p{White_Space}",
+        ('w', true) => "[0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz]",
+        _ => "\\p{L}\\p{N}|\\_|",
+    }
+}
+
+// The edge a product state gets for the zero-width edge `t` of one side,
+// `None` for consuming edges. Capture slots aren't kept.
+fn zero_width(t: &Transition) -> Option<Transition> {
+    match t {
+        Transition::Save(_) => Some(Transition::Epsilon),
+        Transition::Epsilon
+        | Transition::LazyEpsilon
+        | Transition::Assert(_)
+        | Transition::Counter(_) => Some(t.clone()),
+        _ => None,
+    }
+}
+
+// The postfix for the body of an atomic group, which never gives back what
+// it matched. Bodies that can only match one way are kept as they are, and
+// a greedy `*`, `+` or `?` of a single char or class stops taking chars
+// only where the next one isn't taken, which `!` asserts. Other bodies
+// would need backtracking to be undone, which a Thompson NFA can't do.
+fn atomic_body(body: &str) -> Result<String, RegexError> {
+    let tokens = postfix_tokens(body)?;
+    let branches = |t: &PostfixToken| {
+        matches!(
+            t,
+            PostfixToken::Alternate
+                | PostfixToken::Optional(_)
+                | PostfixToken::Star(_)
+                | PostfixToken::Plus(_)
+                | PostfixToken::Group(_)
+                | PostfixToken::Repeat(..)
+        )
+    };
+    if !tokens.iter().any(branches) {
+        return Ok(body.to_string());
+    }
+    let single = match tokens.first() {
+        Some(PostfixToken::Literal(_) | PostfixToken::Set(..) | PostfixToken::Class(..)) => true,
+        Some(PostfixToken::Edge(t)) => t.takes_char_only(),
+        _ => false,
+    };
+    // the quantifier is the last char unless it's lazy
+    let atom = &body[..body.len() - 1];
+    match (single, &tokens[1..]) {
+        (true, [PostfixToken::Star(false)]) => Ok(format!("{atom}*{atom}!.")),
+        (true, [PostfixToken::Plus(false)]) => Ok(format!("{atom}+{atom}!.")),
+        (true, [PostfixToken::Optional(false)]) => Ok(format!("{atom}{atom}!|")),
+        _ => Err(RegexError::Unsupported(
+            "atomic groups other than a greedy repeat of a char or class",
+        )),
+    }
+}
+
+pub fn re2post(re: &str) -> Option<String> {
+    parse_pattern(re, false, true)
+        .ok()
+        .map(|(postfix, _)| postfix)
+}
+
+// `re2post` that also numbers the capture groups by their opening paren and
+// returns one entry per group, which is the group's name if it has one.
+// With `tag` set, every group is closed by `(n)` in the postfix so the
+// compiled graph records where it matched. `ascii` picks between the ASCII
+// and Unicode meanings of `\d`, `\s`, `\w` and the POSIX classes.
+pub(crate) fn parse_pattern(
+    re: &str,
+    tag: bool,
+    ascii: bool,
+) -> Result<(String, Vec<Option<String>>), RegexError> {
+    let mut postfix: String = String::new();
+    let mut groups: Vec<Option<String>> = Vec::new();
+    struct Paren {
+        // byte offset of the `(`
+        at: usize,
+        natom: usize,
+        nalt: usize,
+        // `None` for a non-capturing group
+        group: Option<usize>,
+        // whether case was folded outside of the group
+        fold: bool,
+        // `(?>...)`, whose body starts at this length of the postfix
+        atomic: Option<usize>,
+    }
+    let mut paren: Vec<Paren> = Vec::new();
+    let mut natom = 0usize;
+    let mut nalt = 0usize;
+    // inside a `(?i:...)` group
+    let mut fold = false;
+    let mut chars = re.chars().peekable();
+    // byte offset of the next char
+    let offset =
+        |chars: &Peekable<Chars>| re.len() - chars.clone().map(char::len_utf8).sum::<usize>();
+    while let Some(re_char) = chars.next() {
+        match re_char {
+            '(' => {
+                let at = offset(&chars) - 1;
+                if natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                let mut ahead = chars.clone();
+                let outer_fold = fold;
+                let mut atomic = None;
+                let group = if (ahead.next(), ahead.next()) == (Some('?'), Some(':')) {
+                    // `(?:...)` groups without taking a capture slot
+                    chars = ahead;
+                    None
+                } else if (chars.clone().next(), chars.clone().nth(1)) == (Some('?'), Some('>')) {
+                    // `(?>...)`, an atomic group
+                    chars.nth(1);
+                    atomic = Some(postfix.len());
+                    None
+                } else if chars.peek() == Some(&'?')
+                    && chars.clone().nth(1).is_some_and(|c| c.is_ascii_lowercase())
+                {
+                    // `(?flags:...)`, where `i` for ignoring case is the
+                    // only flag
+                    chars.next();
+                    loop {
+                        match chars.next().ok_or(RegexError::IllegalPattern)? {
+                            ':' => break,
+                            'i' => fold = true,
+                            c if c.is_ascii_alphabetic() => return Err(RegexError::UnknownFlag(c)),
+                            _ => return Err(RegexError::IllegalPattern),
+                        }
+                    }
+                    None
+                } else if chars.peek() == Some(&'?') {
+                    // `(?P<name>...)` or `(?<name>...)`. Anything else after
+                    // `(?` is a quantifier with nothing to repeat.
+                    let at = offset(&chars);
+                    chars.next();
+                    let named = chars.next_if_eq(&'P').is_some();
+                    if chars.next_if_eq(&'<').is_none() {
+                        return Err(if named {
+                            RegexError::IllegalPattern
+                        } else {
+                            RegexError::DanglingQuantifier(at)
+                        });
+                    }
+                    let mut name = String::new();
+                    loop {
+                        match chars.next().ok_or(RegexError::IllegalPattern)? {
+                            '>' => break,
+                            c => name.push(c),
+                        }
+                    }
+                    let mut name_chars = name.chars();
+                    let valid = name_chars
+                        .next()
+                        .is_some_and(|c| c.is_alphabetic() || c == '_')
+                        && name_chars.all(|c| c.is_alphanumeric() || c == '_');
+                    if !valid || groups.contains(&Some(name.clone())) {
+                        return Err(RegexError::IllegalPattern);
+                    }
+                    groups.push(Some(name));
+                    Some(groups.len())
+                } else {
+                    groups.push(None);
+                    Some(groups.len())
+                };
+                paren.push(Paren {
+                    at,
+                    natom,
+                    nalt,
+                    group,
+                    fold: outer_fold,
+                    atomic,
+                });
+                natom = 0;
+                nalt = 0;
+            }
+            '|' => {
+                nalt += 1;
+                if natom == 0 {
+                    // an empty branch is fine inside a group, where it makes
+                    // the other branches optional, but a top-level pattern
+                    // like `a|` is still rejected
+                    if paren.is_empty() {
+                        return Err(RegexError::IllegalPattern);
+                    }
+                    postfix.push_str("\\e");
+                    natom = 1;
+                }
+                while natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                if natom == 1 {
+                    natom = 0;
+                }
+            }
+            ')' => {
+                let Some(p) = paren.pop() else {
+                    return Err(RegexError::UnbalancedParen(offset(&chars) - 1));
+                };
+                if natom == 0 {
+                    // `()` is still an error, `(a|)` ends with an empty branch
+                    if nalt == 0 {
+                        return Err(RegexError::IllegalPattern);
+                    }
+                    postfix.push_str("\\e");
+                    natom = 1;
+                }
+                while natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                while nalt > 0 {
+                    nalt -= 1;
+                    postfix.push('|');
+                }
+                if let Some(start) = p.atomic {
+                    let body = atomic_body(&postfix[start..])?;
+                    postfix.replace_range(start.., &body);
+                }
+                if let Some(group) = p.group.filter(|_| tag) {
+                    postfix.push_str(&format!("({})", group));
+                }
+                natom = p.natom + 1;
+                nalt = p.nalt;
+                fold = p.fold;
+            }
+            '\\' => {
+                let atom = match chars.next().ok_or(RegexError::IllegalPattern)? {
+                    // `\A` and `\z` are the start and end of the input, even
+                    // in multiline mode
+                    c @ ('b' | 'B' | 'A' | 'z') => format!("\\{}", c),
+                    c @ ('d' | 's' | 'w') => shorthand_class(c, ascii).to_string(),
+                    kind @ ('x' | 'u') => literal_atom(
+                        parse_code_point(kind, &mut chars).ok_or(RegexError::IllegalPattern)?,
+                        fold,
+                        ascii,
+                    ),
+                    kind @ ('p' | 'P') => {
+                        if chars.next().ok_or(RegexError::IllegalPattern)? != '{' {
+                            return Err(RegexError::IllegalPattern);
+                        }
+                        let mut name = String::new();
+                        loop {
+                            match chars.next().ok_or(RegexError::IllegalPattern)? {
+                                '}' => break,
+                                c => name.push(c),
+                            }
+                        }
+                        let id = ClassId::from_name(&name).ok_or(RegexError::IllegalPattern)?;
+                        format!("\\{}{{{}}}", kind, id.name())
+                    }
+                    // an escaped operator or other punctuation is a literal
+                    c if !c.is_alphanumeric() => literal_atom(c, fold, ascii),
+                    _ => return Err(RegexError::IllegalPattern),
+                };
+                if natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                postfix.push_str(&atom);
+                natom += 1;
+            }
+            '[' => {
+                let atom = parse_class(&mut chars, ascii, fold)
+                    .ok_or(RegexError::IllegalPattern)?
+                    .postfix();
+                if natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                postfix.push_str(&atom);
+                natom += 1;
+            }
+            '*' | '+' | '?' => {
+                // at the start of the pattern, a group or a branch
+                if natom == 0 {
+                    return Err(RegexError::DanglingQuantifier(
+                        offset(&chars) - re_char.len_utf8(),
+                    ));
+                }
+                postfix.push(re_char);
+                // a trailing `?` makes the quantifier lazy
+                if chars.next_if_eq(&'?').is_some() {
+                    postfix.push('~');
+                }
+            }
+            '{' => {
+                // `{n}`, `{min,}` or `{min,max}`; anything else, like `{,n}`,
+                // stays reserved
+                let at = offset(&chars) - 1;
+                let number = |chars: &mut Peekable<Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits.parse::<usize>().ok()
+                };
+                let min = number(&mut chars).ok_or(RegexError::IllegalPattern)?;
+                let max = match chars.next() {
+                    Some('}') => Some(min),
+                    Some(',') => {
+                        let max = number(&mut chars);
+                        if chars.next() != Some('}') {
+                            return Err(RegexError::IllegalPattern);
+                        }
+                        max
+                    }
+                    _ => return Err(RegexError::IllegalPattern),
+                };
+                if max.is_some_and(|max| max < min) {
+                    return Err(RegexError::IllegalPattern);
+                }
+                if natom == 0 {
+                    return Err(RegexError::DanglingQuantifier(at));
+                }
+                if chars.peek() == Some(&'?') {
+                    return Err(RegexError::Unsupported("lazy counted repetition"));
+                }
+                match max {
+                    Some(max) => postfix.push_str(&format!("{{{},{}}}", min, max)),
+                    None => postfix.push_str(&format!("{{{},}}", min)),
+                }
+            }
+            c if c.is_alphanumeric() || matches!(c, '.' | '^' | '$') => {
+                if natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                // `.` is the concatenation operator in postfix, so the
+                // wildcard is written as `_`
+                match c {
+                    '.' => postfix.push('_'),
+                    '^' | '$' => postfix.push(c),
+                    c => postfix.push_str(&literal_atom(c, fold, ascii)),
+                }
+                natom += 1;
+            }
+            // punctuation with no meaning of its own is a literal; braces
+            // and a stray `]` stay reserved
+            c if !matches!(c, '{' | '}' | ']') && (c.is_ascii_punctuation() || c == ' ') => {
+                if natom > 1 {
+                    natom -= 1;
+                    postfix.push('.');
+                }
+                postfix.push_str(&literal_atom(c, fold, ascii));
+                natom += 1;
+            }
+            _ => {
+                return Err(RegexError::IllegalPattern);
+            }
+        }
+    }
+    // the innermost group that is still open
+    if let Some(p) = paren.pop() {
+        return Err(RegexError::UnbalancedParen(p.at));
+    }
+    // a trailing top-level empty branch, as in `a|`
+    if nalt > 0 && natom == 0 {
+        return Err(RegexError::IllegalPattern);
+    }
+    while natom > 1 {
+        natom -= 1;
+        postfix.push('.');
+    }
+    while nalt > 0 {
+        nalt -= 1;
+        postfix.push('|');
+    }
+    Ok((postfix, groups))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::error::{MatchError, RegexError};
+    use crate::nfa::{Match, StateId};
+
+    #[test]
+    fn test_re_2_post() {
+        assert_eq!("a+b+.", super::re2post("a+b+").unwrap_or_default());
+        assert_eq!(
+            "azd.c.e||+b+.",
+            super::re2post("(a|zdc|e)+b+").unwrap_or_default()
+        );
+        assert_eq!(
+            "azd*.c+.e||+b+.",
+            super::re2post("(a|zd*c+|e)+b+").unwrap_or_default()
+        );
+    }
+
+    #[test]
+    fn test_top_level_alternation() {
+        // every branch flushes its atoms at its `|`, and all the `|` are
+        // flushed together at the end
+        assert_eq!(super::re2post("a|b|c|d").as_deref(), Some("abcd|||"));
+        assert_eq!(super::re2post("ab|cd|ef").as_deref(), Some("ab.cd.ef.||"));
+        let graph = super::NFAGraph::new("a|b|c|d");
+        for text in ["a", "b", "c", "d"] {
+            assert!(graph.is_full_match(text), "{text}");
+        }
+        assert!(!graph.is_match("ab"));
+        let graph = super::NFAGraph::new("ab|cd|ef");
+        for text in ["ab", "cd", "ef"] {
+            assert!(graph.is_full_match(text), "{text}");
+        }
+        assert!(!graph.is_match("ac"));
+        assert!(!graph.is_match("abcd"));
+        assert_eq!(graph.alternatives.len(), 3);
+    }
+
+    #[test]
+    fn test_group_concatenation() {
+        // a group is one more atom: `(` joins the two atoms before it, if
+        // any, and the group itself is joined by whatever comes next
+        for (pattern, post, text) in [
+            ("ab(c)d", "ab.c.d.", "abcd"),
+            ("(a)(b)", "ab.", "ab"),
+            ("a(bc)d", "abc..d.", "abcd"),
+        ] {
+            assert_eq!(super::re2post(pattern).as_deref(), Some(post));
+            let graph = super::NFAGraph::new(pattern);
+            assert!(graph.is_full_match(text), "{pattern}");
+            assert!(!graph.is_match(&text[1..]), "{pattern}");
+            assert!(!graph.is_match(&text[..text.len() - 1]), "{pattern}");
+        }
+        let graph = super::NFAGraph::new("ab(c)d");
+        let caps = graph.captures("abcd").unwrap();
+        assert_eq!(caps.get(1), Some(Match { start: 2, end: 3 }));
+    }
+
+    #[test]
+    fn test_group_quantifiers() {
+        // the quantifier after `)` repeats the whole group, and what follows
+        // is joined to the repeated group
+        for (pattern, post, accepted, rejected) in [
+            (
+                "(ab)+",
+                "ab.+",
+                &["ab", "abab"][..],
+                &["", "a", "abb", "aba"][..],
+            ),
+            ("(a|b)*c", "ab|*c.", &["c", "ac", "babc"], &["", "ab", "cc"]),
+            (
+                "(abc)?d",
+                "ab.c.?d.",
+                &["d", "abcd"],
+                &["abd", "cd", "abcabcd"],
+            ),
+            ("(ab)*d", "ab.*d.", &["d", "ababd"], &["abad", "ad", "ab"]),
+        ] {
+            assert_eq!(super::re2post(pattern).as_deref(), Some(post));
+            let graph = super::NFAGraph::new(pattern);
+            for text in accepted {
+                assert!(graph.is_full_match(text), "{pattern} {text}");
+            }
+            for text in rejected {
+                assert!(!graph.is_full_match(text), "{pattern} {text}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_postfix_tokens() {
+        use super::PostfixToken::*;
+        assert_eq!(
+            super::postfix_tokens("a\\..\\|*~").unwrap(),
+            vec![Literal('a'), Literal('.'), Concat, Literal('|'), Star(true)]
+        );
+        assert_eq!(
+            super::postfix_tokens("a\\").unwrap_err(),
+            super::RegexError::MalformedPostfix
+        );
+        // operator chars only mean operators as tokens, never as literals
+        let graph = super::NFAGraph::from_tokens(&[Literal('.'), Literal('*'), Concat]).unwrap();
+        assert!(graph.is_full_match(".*"));
+        assert!(!graph.is_match("a"));
+
+        // counted repetitions, and the copies they expand to by default
+        assert_eq!(
+            super::postfix_tokens("a{2,}b{0,1}").unwrap(),
+            vec![
+                Literal('a'),
+                Repeat(2, None),
+                Literal('b'),
+                Repeat(0, Some(1))
+            ]
+        );
+        assert!(super::postfix_tokens("a{3,2}").is_err());
+        let expand = |post: &str| super::expand_repeats(super::postfix_tokens(post).unwrap(), 100);
+        assert_eq!(expand("a{1,3}"), super::postfix_tokens("aaa?.?."));
+        assert_eq!(expand("a{2,}"), super::postfix_tokens("aa+."));
+        assert_eq!(expand("ab|{0,}"), super::postfix_tokens("ab|*"));
+        assert_eq!(expand("a{0,0}"), super::postfix_tokens("\\e"));
+        assert_eq!(
+            expand("a{101,101}"),
+            Err(super::RegexError::SizeLimitExceeded(100))
+        );
+    }
+
+    #[test]
+    fn test_escaped_operators() {
+        for (pattern, text, other) in [
+            ("a\\.b", "a.b", "axb"),
+            ("a\\|b", "a|b", "a"),
+            ("a\\*", "a*", "aa"),
+            ("a\\+", "a+", "aa"),
+            ("a\\?", "a?", "a"),
+            ("\\(a\\)", "(a)", "a"),
+        ] {
+            let graph = super::NFAGraph::new(pattern);
+            assert!(graph.is_full_match(text), "{pattern}");
+            assert!(!graph.is_full_match(other), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn test_atomic_group() {
+        use crate::builder::RegexBuilder;
+
+        // the atomic a+ takes every a and gives none back to the last a
+        let build = |pattern| RegexBuilder::new(pattern).build().unwrap();
+        assert!(build("a+a").is_match("aaa"));
+        assert!(!build("(?>a+)a").is_match("aaa"));
+        assert!(build("(?>a+)b").is_match("aaab"));
+        assert!(build("x(?>[ab]*)c").is_match("xabac"));
+        assert!(!build("x(?>[ab]*)b").is_match("xabab"));
+        assert!(!build("(?>a?)a").is_match("a"));
+        assert!(build("(?>a?)a").is_match("aa"));
+        assert!(build("(?>\\d+)!").is_match("12!"));
+        assert!(build("(?>abc)").is_match("abc"));
+        let graph = build("(?>a+)b");
+        assert_eq!(graph.find("caaab").map(|m| m.start), Some(1));
+        assert_eq!(
+            RegexBuilder::new("(?>abc|ab)c").build().unwrap_err(),
+            RegexError::Unsupported("atomic groups other than a greedy repeat of a char or class")
+        );
+    }
+
+    #[test]
+    pub fn test_nfa() {
+        let pattern = "a+b+";
+        let post = super::re2post(pattern).unwrap_or_default();
+        assert_eq!("a+b+.", post);
+        let graph = super::NFAGraph::compile(&post);
+        assert_eq!(graph.states.len(), 8);
+        graph.display();
+        println!(
+            "state {} next is {:?}",
+            3,
+            graph.states.get(&StateId(3)).unwrap().outs
+        );
+        {
+            let mut map = HashMap::new();
+            map.insert(StateId(0), super::Transition::Epsilon);
+            map.insert(StateId(3), super::Transition::Epsilon);
+            assert_eq!(map, graph.states.get(&StateId(1)).unwrap().outs);
+        }
+        {
+            let mut map = HashMap::new();
+            map.insert(StateId(4), super::Transition::Epsilon);
+            map.insert(StateId(7), super::Transition::Epsilon);
+            assert_eq!(map, graph.states.get(&StateId(5)).unwrap().outs);
+        }
+
+        let pattern = "a(b|c)*";
+        let post = super::re2post(pattern).unwrap_or_default();
+        assert_eq!("abc|*.", post);
+        let graph = super::NFAGraph::compile(&post);
+        graph.display();
+        {
+            let mut map = HashMap::new();
+            map.insert(StateId(6), super::Transition::Epsilon);
+            map.insert(StateId(9), super::Transition::Epsilon);
+            assert_eq!(map, graph.states.get(&StateId(7)).unwrap().outs);
+        }
+        {
+            let mut map = HashMap::new();
+            map.insert(StateId(4), super::Transition::Epsilon);
+            map.insert(StateId(2), super::Transition::Epsilon);
+            assert_eq!(map, graph.states.get(&StateId(6)).unwrap().outs);
+        }
+        {
+            let mut map = HashMap::new();
+            map.insert(StateId(6), super::Transition::Epsilon);
+            map.insert(StateId(9), super::Transition::Epsilon);
+            assert_eq!(map, graph.states.get(&StateId(8)).unwrap().outs);
+        }
+        assert_eq!(graph.states.len(), 10);
+    }
+
+    #[test]
+    pub fn test_clone_eq() {
+        let graph = super::NFAGraph::new("a(b|c)*d");
+        let cloned = graph.clone();
+        assert_eq!(cloned, graph);
+        for input in ["ad", "abcbd", "a", "abx", ""] {
+            assert_eq!(cloned.is_match(input), graph.is_match(input));
+        }
+        assert_eq!(super::NFAGraph::new("a+b"), super::NFAGraph::new("a+b"));
+        assert_ne!(super::NFAGraph::new("a+b"), super::NFAGraph::new("a+c"));
+        let mut changed = graph.clone();
+        changed.ends.reverse();
+        assert_eq!(changed, graph);
+        changed.dot_matches_newline = true;
+        assert_ne!(changed, graph);
+    }
+
+    #[test]
+    pub fn test_from_str() {
+        let graph: super::NFAGraph = "a+b+".parse().unwrap();
+        assert!(graph.is_match("aabb"));
+        assert_eq!(
+            "a|".parse::<super::NFAGraph>().unwrap_err(),
+            RegexError::IllegalPattern
+        );
+    }
+
+    #[test]
+    pub fn test_display_transition() {
+        use super::{Assertion, Transition};
+        assert_eq!(Transition::Epsilon.to_string(), "ε");
+        assert_eq!(Transition::LazyEpsilon.to_string(), "ε?");
+        assert_eq!(Transition::Save(3).to_string(), "save 3");
+        assert_eq!(Transition::Char(vec!['a']).to_string(), "a");
+        assert_eq!(Transition::Char(vec!['a', 'b', 'c']).to_string(), "[abc]");
+        assert_eq!(Transition::Byte(vec![0x61]).to_string(), "\\x61");
+        assert_eq!(
+            Transition::Byte(vec![0x0a, 0xff]).to_string(),
+            "[\\x0a\\xff]"
+        );
+        assert_eq!(Transition::Any.to_string(), ".");
+        assert_eq!(
+            Transition::Assert(Assertion::WordBoundary).to_string(),
+            "\\b"
+        );
+        assert_eq!(Transition::Assert(Assertion::EndText).to_string(), "$");
+
+        let dot = super::NFAGraph::new("a\\b").to_dot();
+        assert!(dot.starts_with("digraph nfa {"));
+        assert!(dot.contains("0 -> 1 [label=\"a\"];"));
+        assert!(dot.contains("[label=\"\\\\b\"];"));
+        assert!(dot.contains("[label=\"ε\"];"));
+    }
+
+    #[test]
+    pub fn test_capture_count() {
+        let graph = super::NFAGraph::new("(a)(b(c))");
+        assert_eq!(graph.capture_count(), 3);
+        assert_eq!(graph.capture_names(), vec![None, None, None]);
+        assert_eq!(super::NFAGraph::new("ab*").capture_count(), 0);
+        let graph = crate::builder::RegexBuilder::new("x(y|z)+")
+            .build()
+            .unwrap();
+        assert_eq!(graph.capture_count(), 1);
+    }
+
+    #[test]
+    pub fn test_match() {
+        {
+            let pattern = "a+b+";
+            let post = super::re2post(pattern).unwrap_or_default();
+            let graph = super::NFAGraph::compile(&post);
+            assert!(graph.is_match("aaaabbb"));
+            let pattern = "a(b|c)*";
+            let post = super::re2post(pattern).unwrap_or_default();
+            let graph = super::NFAGraph::compile(&post);
+            assert!(graph.is_match("abbcbbcc"));
+            assert!(!graph.is_match("bcbbcc"));
+        }
+    }
+
+    #[test]
+    pub fn test_active_states() {
+        let graph = super::NFAGraph::new("a+b+");
+        let active = graph.active_states("aabb", 2);
+        // the a-loop is still live, the b-accept is not reached yet
+        assert!(active.contains(&StateId(0)));
+        assert!(active.contains(&StateId(1)));
+        assert!(!active.contains(&graph.ends[0]));
+        let active = graph.active_states("aabb", 4);
+        assert!(active.contains(&graph.ends[0]));
+    }
+
+    #[test]
+    pub fn test_trace() {
+        // a: 0 -a-> 1, and the + wraps it as 2 -> 0, 1 -> 0 | 3
+        let graph = super::NFAGraph::new("a+");
+        let looping = vec![StateId(0), StateId(1), StateId(3)];
+        assert_eq!(
+            graph.trace("aa"),
+            vec![('a', looping.clone()), ('a', looping.clone())]
+        );
+        assert_eq!(graph.trace("aba"), vec![('a', looping), ('b', vec![])]);
+    }
+
+    #[test]
+    pub fn test_explain() {
+        use std::collections::BTreeSet;
+
+        let graph = super::NFAGraph::new("abc");
+        let explanation = graph.explain("abx");
+        assert!(!explanation.matched);
+        assert_eq!(explanation.position, Some(2));
+        assert_eq!(explanation.expected, BTreeSet::from(['c']));
+        assert!(!explanation.open);
+        // running out of input fails at its end
+        assert_eq!(graph.explain("ab").position, Some(2));
+        assert_eq!(graph.explain("ab").expected, BTreeSet::from(['c']));
+        // too much input leaves nothing to expect
+        let explanation = graph.explain("abcd");
+        assert_eq!(explanation.position, Some(3));
+        assert!(explanation.expected.is_empty());
+        assert!(graph.explain("abc").matched);
+        assert_eq!(graph.explain("abc").position, None);
+
+        let graph = super::NFAGraph::new("a(b|c)[^x]");
+        let explanation = graph.explain("éa");
+        assert_eq!(explanation.position, Some(0));
+        assert_eq!(explanation.expected, BTreeSet::from(['a']));
+        let explanation = graph.explain("aé");
+        assert_eq!(explanation.position, Some(1));
+        assert_eq!(explanation.expected, BTreeSet::from(['b', 'c']));
+        assert!(graph.explain("ab").open);
+    }
+
+    #[test]
+    pub fn test_move_dedup() {
+        // point both branches of a|a at the same target
+        let mut graph = super::NFAGraph::new("a|a");
+        let mut sources: Vec<StateId> = graph
+            .states
+            .values()
+            .filter(|state| state.outs.values().any(|t| !t.is_epsilon()))
+            .map(|state| state.id)
+            .collect();
+        sources.sort();
+        let target = *graph.states[&sources[0]].outs.keys().next().unwrap();
+        let other = graph.states.get_mut(&sources[1]).unwrap();
+        other.outs.clear();
+        other
+            .outs
+            .insert(target, super::Transition::Char(vec!['a']));
+        graph.index_chars();
+
+        let moved = graph.move2('a', &[sources[0], sources[1], sources[0]]);
+        assert_eq!(moved, vec![target]);
+        let closure = graph.closure(vec![target, target], super::Context::default());
+        let mut unique = closure.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(closure.len(), unique.len());
+        assert!(graph.is_match("a"));
+    }
+
+    #[test]
+    pub fn test_compile_subgraph() {
+        let mut graph = super::NFAGraph::empty();
+        let frag1 = super::NFAGraph::compile_subgraph("a+", &mut graph);
+        let frag2 = super::NFAGraph::compile_subgraph("b+", &mut graph);
+        assert_eq!(graph.states.len(), 8);
+        for next in frag1.end.iter() {
+            let state = graph.states.get_mut(next).unwrap();
+            state.outs.insert(frag2.start, super::Transition::Epsilon);
+        }
+        graph.start = frag1.start;
+        graph.ends = frag2.end;
+        assert!(graph.is_match("aabb"));
+        assert!(!graph.is_match("aa"));
+        assert!(!graph.is_match("bbaa"));
+    }
+
+    #[test]
+    pub fn test_concat_alternate() {
+        let graph = super::NFAGraph::new("a+").concat(super::NFAGraph::new("b+"));
+        assert!(graph.is_full_match("aabb"));
+        assert!(!graph.is_match("aa"));
+        assert!(!graph.is_match("bbaa"));
+
+        let graph = super::NFAGraph::new("a").alternate(super::NFAGraph::new("b"));
+        assert!(graph.is_full_match("a"));
+        assert!(graph.is_full_match("b"));
+        assert!(!graph.is_match("c"));
+        assert_eq!(graph, super::NFAGraph::new("a|b"));
+
+        // groups of the second graph are numbered after those of the first
+        let graph = super::NFAGraph::new("(a)|(?P<x>b)").alternate(super::NFAGraph::new("(c)"));
+        assert_eq!(graph.capture_count(), 3);
+        assert_eq!(
+            graph.find_tagged("xc"),
+            Some((2, Match { start: 1, end: 2 }))
+        );
+        let caps = graph.captures("c").unwrap();
+        assert_eq!((caps.get(1), caps.str(3)), (None, Some("c")));
+        let graph = super::NFAGraph::new("(a)").concat(super::NFAGraph::new("(b)"));
+        let caps = graph.captures("ab").unwrap();
+        assert_eq!((caps.str(1), caps.str(2)), (Some("a"), Some("b")));
+    }
+
+    #[test]
+    pub fn test_union() {
+        let graph = super::NFAGraph::union(&["a+", "b+"]).unwrap();
+        assert!(graph.is_match("aaa"));
+        assert!(graph.is_match("bbb"));
+        assert!(!graph.is_match("ab"));
+        assert!(!graph.is_match(""));
+        assert_eq!(graph.find_tagged("xbba").map(|(i, _)| i), Some(1));
+
+        // capture groups are numbered across the patterns
+        let graph = super::NFAGraph::union(&["(a)x", "(b)(c)"]).unwrap();
+        assert_eq!(graph.capture_count(), 3);
+        let caps = graph.captures("bc").unwrap();
+        assert_eq!(caps.get(3), Some(Match { start: 1, end: 2 }));
+
+        let graph = super::NFAGraph::union(&[]).unwrap();
+        assert!(!graph.is_match(""));
+        assert_eq!(
+            super::NFAGraph::union(&["a", "(b"]).unwrap_err(),
+            RegexError::UnbalancedParen(0)
+        );
+    }
+
+    #[test]
+    pub fn test_intersect() {
+        let graph = super::NFAGraph::new("a+").intersect(&super::NFAGraph::new("(aa)+"));
+        assert!(graph.is_valid());
+        assert!(graph.is_match("aa"));
+        assert!(graph.is_match("aaaa"));
+        assert!(!graph.is_match("aaa"));
+        assert!(!graph.is_match(""));
+
+        let words = super::NFAGraph::new("[a-z]+");
+        let graph = words.intersect(&super::NFAGraph::new(".*x.*"));
+        assert!(graph.is_match("fox"));
+        assert!(!graph.is_match("dog"));
+        assert!(!graph.is_match("x1"));
+        let graph = words.intersect(&super::NFAGraph::new("[^aeiou]*"));
+        assert!(graph.is_match("rhythm"));
+        assert!(!graph.is_match("rhyme"));
+        let graph = super::NFAGraph::new("\\ba.").intersect(&super::NFAGraph::new(".b"));
+        assert!(graph.is_match("ab"));
+        assert!(!graph.is_match("aa"));
+        // disjoint languages leave a graph that matches nothing
+        let graph = super::NFAGraph::new("a+").intersect(&super::NFAGraph::new("b+"));
+        assert!(graph.is_valid());
+        assert!(!graph.is_match("a") && !graph.is_match("b"));
+
+        // the wildcard and negated sets against a Unicode class
+        let letters = super::NFAGraph::new("\\p{L}+");
+        let graph = super::NFAGraph::new(".").intersect(&super::NFAGraph::new("\\p{L}"));
+        assert!(graph.is_valid());
+        assert!(graph.is_match("\u{e9}") && !graph.is_match("3") && !graph.is_match("\n"));
+        let graph = letters.intersect(&super::NFAGraph::new("[^0-9]+"));
+        assert!(graph.is_valid());
+        assert!(graph.is_match("ab") && !graph.is_match("a1"));
+        let graph = super::NFAGraph::new("[^x]").intersect(&super::NFAGraph::new("\\P{L}"));
+        assert!(graph.is_match("3") && !graph.is_match("x") && !graph.is_match("a"));
+
+        // two different Unicode classes have no single edge for both, nor
+        // does a class with some of the chars a set leaves out
+        let graph = super::NFAGraph::new("\\p{L}").intersect(&super::NFAGraph::new("\\p{N}"));
+        assert!(!graph.is_valid());
+        assert!(!letters.intersect(&super::NFAGraph::new("[^a]+")).is_valid());
+    }
+
+    #[test]
+    pub fn test_trim() {
+        // the Thompson construction of a(b|c)* has no dead states itself, so
+        // leave an unconnected fragment and a dead-end branch behind
+        let mut graph = super::NFAGraph::new("a(b|c)*");
+        let untrimmed = graph.states.len();
+        super::NFAGraph::compile_subgraph("d+", &mut graph);
+        let dead = super::NFAGraph::compile_subgraph("xy.", &mut graph);
+        let start = graph.start;
+        graph
+            .states
+            .get_mut(&start)
+            .unwrap()
+            .outs
+            .insert(dead.start, super::Transition::Epsilon);
+        assert!(graph.is_valid());
+        let before = graph.clone();
+
+        graph.trim();
+        assert_eq!(graph.states.len(), untrimmed);
+        assert!(graph.states.len() < before.states.len());
+        assert_eq!(graph.last_id, graph.states.len());
+        for id in graph.states.keys() {
+            assert!(id.0 < graph.states.len());
+        }
+        for input in ["a", "abcb", "ac", "axy", "xy", "d", "", "abd"] {
+            assert_eq!(graph.is_match(input), before.is_match(input), "{input}");
+            assert_eq!(graph.find(input), before.find(input), "{input}");
+        }
+
+        let mut graph = super::NFAGraph::new("ab|cd");
+        graph.trim();
+        assert_eq!(
+            graph.find_tagged("xcd"),
+            Some((1, Match { start: 1, end: 3 }))
+        );
+
+        // graphs without a start state are left alone instead of panicking
+        let mut graph = super::NFAGraph::empty();
+        graph.trim();
+        assert_eq!(graph, super::NFAGraph::empty());
+        let mut graph = super::NFAGraph::compile("a|");
+        assert!(!graph.is_valid());
+        let before = graph.clone();
+        graph.trim();
+        assert_eq!(graph, before);
+    }
+
+    #[test]
+    pub fn test_find() {
+        let graph = super::NFAGraph::new("a+b");
+        assert_eq!(graph.find("xxaabab"), Some(Match { start: 2, end: 5 }));
+        assert_eq!(graph.find("xxaa"), None);
+        assert_eq!(
+            graph.find_iter("aab ab b").collect::<Vec<_>>(),
+            vec![Match { start: 0, end: 3 }, Match { start: 4, end: 6 }]
+        );
+        let mut matches = graph.find_iter("ab ab ab").take(2);
+        assert_eq!(matches.next(), Some(Match { start: 0, end: 2 }));
+        assert_eq!(matches.next(), Some(Match { start: 3, end: 5 }));
+        assert_eq!(matches.next(), None);
+        let graph = super::NFAGraph::new("a*");
+        assert_eq!(graph.find_iter("baa").count(), 3);
+    }
+
+    #[test]
+    pub fn test_find_multibyte() {
+        // spans are byte offsets, so they slice the input at char boundaries
+        let s = "xéého";
+        let graph = super::NFAGraph::new("é+");
+        let m = graph.find(s).unwrap();
+        assert_eq!(m, Match { start: 1, end: 5 });
+        assert_eq!(m.as_str(s), "éé");
+        assert_eq!(&s[m.start..m.end], "éé");
+        let m = super::NFAGraph::new("h.").find(s).unwrap();
+        assert_eq!(m.as_str(s), "ho");
+        for (pattern, s) in [
+            ("[éあ]+", "aéあé😀b"),
+            (".", "😀"),
+            ("\\u{1F600}b|é", "あ😀b"),
+        ] {
+            let graph = super::NFAGraph::new(pattern);
+            for m in graph.find_iter(s) {
+                assert!(s.is_char_boundary(m.start) && s.is_char_boundary(m.end));
+                assert!(graph.is_full_match(m.as_str(s)), "{pattern} {s}");
+            }
+            assert!(graph.find(s).is_some(), "{pattern} {s}");
+        }
+    }
+
+    #[test]
+    pub fn test_literal_prefix() {
+        assert_eq!(super::NFAGraph::new("foobar+").prefix, "foobar");
+        assert_eq!(super::NFAGraph::new("ab(c|d)").prefix, "ab");
+        assert_eq!(super::NFAGraph::new("a*b").prefix, "");
+        assert_eq!(super::NFAGraph::new("\\bab").prefix, "");
+
+        let graph = super::NFAGraph::new("foobar+");
+        let mut haystack = "fooba foob ".repeat(200);
+        let start = haystack.len();
+        haystack.push_str("foobarrr fooba");
+        assert_eq!(
+            graph.find(&haystack),
+            Some(Match {
+                start,
+                end: start + 8
+            })
+        );
+        assert_eq!(graph.find_iter(&haystack).count(), 1);
+        assert_eq!(graph.find("foob"), None);
+    }
+
+    #[test]
+    pub fn test_literal_fast_path() {
+        let graph = super::NFAGraph::new("hello");
+        assert!(graph.literal);
+        assert!(graph.is_match("hello"));
+        assert!(!graph.is_match("hello!"));
+        assert_eq!(
+            graph.find_iter("hello, hellohello").collect::<Vec<_>>(),
+            vec![
+                Match { start: 0, end: 5 },
+                Match { start: 7, end: 12 },
+                Match { start: 12, end: 17 }
+            ]
+        );
+        assert_eq!(graph.find_at("hello", 1), None);
+        assert_eq!(
+            super::NFAGraph::new("né").find("ané"),
+            Some(Match { start: 1, end: 4 })
+        );
+        for pattern in ["hel+o", "hello|x", "^hello", "hell.", "hello*"] {
+            assert!(!super::NFAGraph::new(pattern).literal, "{pattern}");
+        }
+        // groups don't get in the way, and captures still run the graph
+        let graph = super::NFAGraph::new("he(ll)o");
+        assert!(graph.literal);
+        let caps = graph.captures("oh hello").unwrap();
+        assert_eq!(caps.get(1), Some(Match { start: 5, end: 7 }));
+    }
+
+    #[test]
+    pub fn test_coalesce_epsilons() {
+        let inputs = ["a", "ab", "abcbc", "ac", "b", "", "abx"];
+        for pattern in [
+            "a(b|c)*",
+            "a+b+",
+            "(a|b)*abb",
+            "(?P<x>a|b)c?",
+            "a*?b",
+            "\\ba|b",
+        ] {
+            let graph = super::NFAGraph::new(pattern);
+            let mut coalesced = graph.clone();
+            coalesced.coalesce_epsilons();
+            assert!(coalesced.states.len() <= graph.states.len(), "{pattern}");
+            for input in inputs {
+                assert_eq!(
+                    coalesced.is_match(input),
+                    graph.is_match(input),
+                    "{pattern}"
+                );
+                assert_eq!(coalesced.find(input), graph.find(input), "{pattern}");
+                assert_eq!(
+                    coalesced.captures(input).map(|caps| caps.get(1)),
+                    graph.captures(input).map(|caps| caps.get(1)),
+                    "{pattern}"
+                );
+            }
+        }
+        let graph = super::NFAGraph::new("a(b|c)*");
+        let mut coalesced = graph.clone();
+        coalesced.coalesce_epsilons();
+        assert!(coalesced.states.len() < graph.states.len());
+        for end in coalesced.ends.iter() {
+            assert!(coalesced.states.contains_key(end));
+        }
+    }
+
+    #[test]
+    pub fn test_match_accessors() {
+        let text = "xx añob";
+        let m = super::NFAGraph::new("añ+o").find(text).unwrap();
+        assert_eq!(m.as_str(text), "año");
+        assert_eq!(m.len(), 4);
+        assert!(!m.is_empty());
+        let m = super::NFAGraph::new("b*").find(text).unwrap();
+        assert_eq!(m.as_str(text), "");
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    pub fn test_quantifiers() {
+        assert!(super::NFAGraph::new("a*").is_match(""));
+        assert!(!super::NFAGraph::new("a+").is_match(""));
+        let graph = super::NFAGraph::new("a*b");
+        assert!(graph.is_match("aab"));
+        assert!(graph.is_match("b"));
+        let graph = super::NFAGraph::new("ab?c");
+        assert!(graph.is_match("abc"));
+        assert!(graph.is_match("ac"));
+        assert!(!graph.is_match("abbc"));
+    }
+
+    #[test]
+    pub fn test_lazy() {
+        assert_eq!("a_*~.b.", super::re2post("a.*?b").unwrap_or_default());
+        assert_eq!("ab+~.", super::re2post("ab+?").unwrap_or_default());
+
+        let graph = super::NFAGraph::new("a.*?b");
+        assert_eq!(graph.find("axbxb"), Some(Match { start: 0, end: 3 }));
+        // is_match doesn't care about laziness
+        assert!(graph.is_match("axbxb"));
+        let graph = super::NFAGraph::new("a.*b");
+        assert_eq!(graph.find("axbxb"), Some(Match { start: 0, end: 5 }));
+
+        let graph = super::NFAGraph::new("a+?");
+        assert_eq!(graph.find("aaa"), Some(Match { start: 0, end: 1 }));
+        let graph = super::NFAGraph::new("ab??");
+        assert_eq!(graph.find("ab"), Some(Match { start: 0, end: 1 }));
+        // greedy parts after a lazy one still extend the match
+        let graph = super::NFAGraph::new("a+?b+");
+        assert_eq!(graph.find("aabb"), Some(Match { start: 0, end: 4 }));
+    }
+
+    #[test]
+    pub fn test_is_full_match() {
+        let graph = super::NFAGraph::new("a+b+");
+        assert!(graph.is_full_match("aabb"));
+        assert!(!graph.is_full_match("aabbc"));
+        assert!(!graph.is_full_match("caabb"));
+        assert!(graph.find("aabbc").is_some());
+        assert!(super::NFAGraph::new("a*").is_full_match(""));
+        assert!(super::NFAGraph::new("^a$").is_full_match("a"));
+
+        // counted loops and normalization go through the same matcher
+        let graph = crate::builder::RegexBuilder::new("a{2,3}b")
+            .counted_loops(true)
+            .build()
+            .unwrap();
+        assert!(graph.is_full_match("aab") && graph.is_full_match("aaab"));
+        assert!(!graph.is_full_match("ab") && !graph.is_full_match("aaaab"));
+    }
+
+    #[test]
+    pub fn test_matches_empty() {
+        assert!(super::NFAGraph::new("a*").matches_empty());
+        assert!(!super::NFAGraph::new("a+").matches_empty());
+        assert!(super::NFAGraph::new("(a|b?)c*").matches_empty());
+        assert!(super::NFAGraph::new("^$").matches_empty());
+        assert!(!super::NFAGraph::new("\\b").matches_empty());
+    }
+
+    #[test]
+    pub fn test_edges() {
+        let graph = super::NFAGraph::new("a");
+        let chars: Vec<_> = graph
+            .edges()
+            .into_iter()
+            .filter(|(_, _, t)| matches!(t, super::Transition::Char(_)))
+            .collect();
+        assert_eq!(chars.len(), 1);
+        let (from, to, transition) = &chars[0];
+        assert_eq!(*from, graph.start_id());
+        assert_eq!(graph.end_ids(), vec![*to]);
+        assert_eq!(*transition, super::Transition::Char(vec!['a']));
+
+        let graph = super::NFAGraph::new("a+b+");
+        assert_eq!(graph.edges().len(), graph.stats().transition_count);
+        assert!(graph
+            .edges()
+            .windows(2)
+            .all(|w| (w[0].0, w[0].1) < (w[1].0, w[1].1)));
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let graph = super::NFAGraph::new("a+b+");
+        assert_eq!(
+            graph.stats(),
+            super::GraphStats {
+                state_count: 8,
+                transition_count: 9,
+                epsilon_count: 7,
+                char_edge_count: 2,
+            }
+        );
+        let mut graph = super::NFAGraph::new("(a)|^b");
+        let before = graph.stats();
+        assert_eq!(before.transition_count, before.epsilon_count + 3);
+        graph.coalesce_epsilons();
+        assert!(graph.stats().state_count < before.state_count);
+        assert_eq!(graph.stats().char_edge_count, 2);
+    }
+
+    #[test]
+    pub fn test_alphabet() {
+        let graph = super::NFAGraph::new("a(b|c)*");
+        assert_eq!(graph.alphabet(), ['a', 'b', 'c'].into_iter().collect());
+        assert!(!graph.has_wildcard());
+        let graph = super::NFAGraph::new("x.\\by");
+        assert_eq!(graph.alphabet(), ['x', 'y'].into_iter().collect());
+        assert!(graph.has_wildcard());
+    }
+
+    #[test]
+    pub fn test_reverse() {
+        for pattern in ["a+b+", "a(b|c)*d", "ab?c", "^ab", "a\\b", "x(|y)z"] {
+            let graph = super::NFAGraph::new(pattern);
+            let reversed = graph.reverse();
+            for input in ["aabb", "abcbd", "ac", "abc", "ab", "a", "xz", "xyz", "zyx"] {
+                let backwards: String = input.chars().rev().collect();
+                assert_eq!(
+                    reversed.is_match(&backwards),
+                    graph.is_match(input),
+                    "{pattern} {input}"
+                );
+            }
+        }
+        // the first match to end (c) doesn't start leftmost
+        let graph = super::NFAGraph::new("abcd|c");
+        assert_eq!(graph.find("xabcd"), Some(Match { start: 1, end: 5 }));
+        assert_eq!(graph.find("xabce"), Some(Match { start: 3, end: 4 }));
+        let graph = super::NFAGraph::new("ab|bcd");
+        assert_eq!(graph.find("abcd"), Some(Match { start: 0, end: 2 }));
+        assert_eq!(
+            graph.find_shortest("abcd"),
+            Some(Match { start: 0, end: 2 })
+        );
+
+        // built once with the graph, and not for counted loops
+        assert_eq!(graph.reversed.as_deref(), Some(&graph.reverse()));
+        let graph = crate::builder::RegexBuilder::new("a{2,3}")
+            .counted_loops(true)
+            .build()
+            .unwrap();
+        assert!(graph.reversed.is_none());
+        // options changed by hand after building still count
+        let mut graph = super::NFAGraph::new("a.b");
+        assert_eq!(graph.find("xa\nb"), None);
+        graph.dot_matches_newline = true;
+        assert_eq!(graph.find("xa\nb"), Some(Match { start: 1, end: 4 }));
+    }
+
+    #[test]
+    pub fn test_find_shortest() {
+        let graph = super::NFAGraph::new("a+");
+        assert_eq!(graph.find_shortest("aaa"), Some(Match { start: 0, end: 1 }));
+        assert_eq!(graph.find("aaa"), Some(Match { start: 0, end: 3 }));
+        let graph = super::NFAGraph::new("ab|a");
+        assert_eq!(graph.find_shortest("xab"), Some(Match { start: 1, end: 2 }));
+        assert_eq!(super::NFAGraph::new("b").find_shortest("aaa"), None);
+    }
+
+    #[test]
+    pub fn test_find_in_reader() {
+        let graph = super::NFAGraph::new("b+");
+        let text = "abc\nxyz\r\nbb\n\nlast b";
+        let lines: Vec<(usize, String)> =
+            graph.find_in_reader(std::io::Cursor::new(text)).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (1, "abc".to_string()),
+                (3, "bb".to_string()),
+                (5, "last b".to_string())
+            ]
+        );
+        let graph = super::NFAGraph::new("^z$");
+        assert_eq!(graph.find_in_reader("xyz\r\nz\r\n".as_bytes()).count(), 1);
+    }
+
+    #[test]
+    pub fn test_count_matches() {
+        let graph = super::NFAGraph::new("a+");
+        assert_eq!(graph.count_matches("a aa aaa"), 3);
+        assert_eq!(graph.count_matches("bbb"), 0);
+        // empty matches are counted once per position, like find_iter
+        let graph = super::NFAGraph::new("a*");
+        assert_eq!(graph.count_matches("ab"), 3);
+        assert_eq!(graph.count_matches("ab"), graph.find_iter("ab").count());
+    }
+
+    #[test]
+    pub fn test_find_overlapping() {
+        let graph = super::NFAGraph::new("a+");
+        let spans: Vec<(usize, usize)> = graph
+            .find_overlapping("aaa")
+            .iter()
+            .map(|m| (m.start, m.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)]);
+        assert_eq!(graph.find_iter("aaa").count(), 1);
+        let graph = super::NFAGraph::new("ab|b");
+        assert_eq!(
+            graph.find_overlapping("xab"),
+            vec![Match { start: 1, end: 3 }, Match { start: 2, end: 3 }]
+        );
+    }
+
+    #[test]
+    pub fn test_find_tagged() {
+        let graph = super::NFAGraph::new("(cat)|(dog)");
+        assert_eq!(graph.alternatives.len(), 2);
+        assert_eq!(
+            graph.find_tagged("a dog here"),
+            Some((1, Match { start: 2, end: 5 }))
+        );
+        assert_eq!(
+            graph.find_tagged("cat dog"),
+            Some((0, Match { start: 0, end: 3 }))
+        );
+        assert_eq!(graph.find_tagged("cow"), None);
+
+        let graph = super::NFAGraph::new("a+|b(c|d)|e");
+        assert_eq!(graph.alternatives.len(), 3);
+        assert_eq!(
+            graph.find_tagged("xxbd"),
+            Some((1, Match { start: 2, end: 4 }))
+        );
+        assert_eq!(
+            graph.find_tagged("e"),
+            Some((2, Match { start: 0, end: 1 }))
+        );
+
+        let graph = super::NFAGraph::new("a(b|c)");
+        assert!(graph.alternatives.is_empty());
+        assert_eq!(
+            graph.find_tagged("ac"),
+            Some((0, Match { start: 0, end: 2 }))
+        );
+    }
+
+    #[test]
+    pub fn test_word_boundary() {
+        assert_eq!(
+            "\\bw.o.r.d.\\b.",
+            super::re2post("\\bword\\b").unwrap_or_default()
+        );
+        let graph = super::NFAGraph::new("\\bword\\b");
+        assert_eq!(graph.find("a word here"), Some(Match { start: 2, end: 6 }));
+        assert_eq!(graph.find("crossword"), None);
+        assert_eq!(graph.find("words"), None);
+        // string start and end count as non-word neighbours
+        assert!(graph.is_match("word"));
+        assert_eq!(graph.find("word"), Some(Match { start: 0, end: 4 }));
+        assert_eq!(graph.find("_word"), None);
+
+        let graph = super::NFAGraph::new("\\Bor\\B");
+        assert_eq!(graph.find("word"), Some(Match { start: 1, end: 3 }));
+        assert_eq!(graph.find("or"), None);
+        let graph = super::NFAGraph::new("a\\bb");
+        assert!(!graph.is_match("ab"));
+        let graph = super::NFAGraph::new("a\\Bb");
+        assert!(graph.is_match("ab"));
+    }
+
+    #[test]
+    pub fn test_empty_branch() {
+        assert_eq!("a\\e|b.", super::re2post("(a|)b").unwrap_or_default());
+        assert_eq!("\\eb|", super::re2post("(|b)").unwrap_or_default());
+        let graph = super::NFAGraph::new("(a|)b");
+        assert!(graph.is_match("ab"));
+        assert!(graph.is_match("b"));
+        assert!(!graph.is_match("aab"));
+        let graph = super::NFAGraph::new("x(|b)");
+        assert!(graph.is_match("x"));
+        assert!(graph.is_match("xb"));
+        assert_eq!(graph.find("xb"), Some(Match { start: 0, end: 2 }));
+        // top-level empty branches and empty groups stay errors
+        assert_eq!(super::re2post("a|"), None);
+        assert_eq!(super::re2post("|a"), None);
+        assert_eq!(super::re2post("()"), None);
+    }
+
+    #[test]
+    pub fn test_hex_escapes() {
+        assert_eq!("ab.", super::re2post("\\x61\\x62").unwrap_or_default());
+        let graph = super::NFAGraph::new("\\x61\\x62");
+        assert!(graph.is_match("ab"));
+        let graph = super::NFAGraph::new("x\\u{1F600}+");
+        assert!(graph.is_match("x\u{1F600}\u{1F600}"));
+        assert_eq!(graph.find("a x\u{1F600}"), Some(Match { start: 2, end: 7 }));
+        // escaped operators are plain literals
+        let graph = super::NFAGraph::new("a\\x2e\\x7c");
+        assert!(graph.is_match("a.|"));
+        assert!(!graph.is_match("ab|"));
+        assert!(super::NFAGraph::new("\\x00").is_match("\0"));
+        for bad in [
+            "\\xZZ",
+            "\\x6",
+            "\\u{}",
+            "\\u{110000}",
+            "\\u{D800}",
+            "\\u0041",
+            "\\u{1234567}",
+        ] {
+            assert_eq!(
+                bad.parse::<super::NFAGraph>().unwrap_err(),
+                RegexError::IllegalPattern,
+                "{bad}"
+            );
+        }
+        let graph = crate::builder::RegexBuilder::new("\\u{e9}")
+            .bytes(true)
+            .build()
+            .unwrap();
+        assert!(graph.is_match_bytes("é".as_bytes()));
+    }
+
+    #[test]
+    pub fn test_find_tagged_escaped_bar() {
+        let graph = super::NFAGraph::new("a|\\x7c");
+        assert_eq!(
+            graph.find_tagged("|"),
+            Some((1, Match { start: 0, end: 1 }))
+        );
+    }
+
+    #[test]
+    pub fn test_classes() {
+        assert_eq!("[abc]x.", super::re2post("[a-c]x").unwrap_or_default());
+        assert_eq!("[^-\\]\\^]", super::re2post("[^]^-]").unwrap_or_default());
+        let graph = super::NFAGraph::new("[a-cx]+");
+        assert!(graph.is_match("abxc"));
+        assert!(!graph.is_match("abd"));
+        let graph = super::NFAGraph::new("[^ab]c");
+        assert!(graph.is_match("xc"));
+        assert!(graph.is_match("\nc"));
+        assert!(!graph.is_match("ac"));
+        assert_eq!(graph.find("abxc"), Some(Match { start: 2, end: 4 }));
+        assert!(graph.has_wildcard());
+        assert!(super::NFAGraph::new("[\\]\\x41]+").is_match("]A]"));
+        for bad in ["[a", "[z-a]", "[\\q]", "[^]", "[\\u{0}-\\u{10FFFF}]"] {
+            assert_eq!(super::re2post(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    pub fn test_char_class() {
+        use super::CharClass;
+
+        let class = CharClass::parse("[a-z_]").unwrap();
+        assert!(class.contains('q'));
+        assert!(class.contains('_'));
+        assert!(!class.contains('A'));
+        let class = CharClass::parse("[^[:digit:]\\]]").unwrap();
+        assert!(class.contains('x'));
+        assert!(!class.contains('7'));
+        assert!(!class.contains(']'));
+        assert!(CharClass::parse("[]a]").unwrap().contains(']'));
+        for bad in ["[a", "[z-a]", "[^]", "a-z", "[a]b", ""] {
+            assert_eq!(
+                CharClass::parse(bad),
+                Err(super::RegexError::IllegalPattern),
+                "{bad}"
+            );
+        }
+        // the sizes of these ranges add up past `u32::MAX`
+        let huge = format!("[{}]", "\\u{0}-\\u{10FFFF}".repeat(4000));
+        assert_eq!(
+            CharClass::parse(&huge),
+            Err(super::RegexError::IllegalPattern)
+        );
+    }
+
+    #[test]
+    pub fn test_posix_classes() {
+        let graph = super::NFAGraph::new("[[:digit:]]+");
+        assert!(graph.is_match("42"));
+        assert!(!graph.is_match("4a"));
+        let graph = super::NFAGraph::new("[[:digit:]_]+");
+        assert!(graph.is_match("4_2"));
+        let graph = super::NFAGraph::new("[[:upper:]][[:lower:]]+[[:space:]]");
+        assert!(graph.is_match("Word\t"));
+        assert!(!graph.is_match("word "));
+        assert!(super::NFAGraph::new("[^[:alnum:]]").is_match("-"));
+        assert!(super::NFAGraph::new("[[:alpha:]]").is_match("q"));
+        assert_eq!(
+            "[[:foo:]]".parse::<super::NFAGraph>().unwrap_err(),
+            RegexError::IllegalPattern
+        );
+        assert_eq!(super::re2post("[[:digit]]"), None);
+    }
+
+    #[test]
+    pub fn test_unicode_classes() {
+        assert_eq!(
+            "\\p{L}+",
+            super::re2post("\\p{Letter}+").unwrap_or_default()
+        );
+        let graph = super::NFAGraph::new("\\p{L}");
+        assert!(graph.is_match("é"));
+        assert!(graph.is_match("あ"));
+        assert!(!graph.is_match("3"));
+        let graph = super::NFAGraph::new("\\p{L}+");
+        assert_eq!(graph.find("12 héllo!"), Some(Match { start: 3, end: 9 }));
+        let graph = super::NFAGraph::new("\\P{N}\\p{N}");
+        assert!(graph.is_match("x٣"));
+        assert!(!graph.is_match("33"));
+        assert!(super::NFAGraph::new("\\p{Lu}\\p{Ll}").is_match("Éa"));
+        let graph = super::NFAGraph::new("\\p{Z}");
+        assert!(graph.is_match(" ") && graph.is_match("\u{a0}") && graph.is_match("\u{2028}"));
+        assert!(!graph.is_match("\t") && !graph.is_match("\n"));
+        assert!(super::NFAGraph::new("\\p{White_Space}").is_match("\t"));
+        // a negated set can hold Unicode classes in postfix
+        let graph = super::NFAGraph::from_postfix("[^x\\p{N}\\p{Z}]").unwrap();
+        assert!(graph.is_match("y"));
+        assert!(!graph.is_match("x") && !graph.is_match("٣") && !graph.is_match(" "));
+        let edge = graph.states[&graph.start].outs.values().next().unwrap();
+        assert_eq!(edge.to_string(), "[^x\\p{N}\\p{Z}]");
+        assert!(super::NFAGraph::from_postfix("[^\\p{Foo}]").is_err());
+        assert!(super::NFAGraph::from_postfix("[^\\p]")
+            .unwrap()
+            .is_match("q"));
+        assert_eq!(super::re2post("\\p{Foo}"), None);
+        assert_eq!(super::re2post("\\pL"), None);
+        assert_eq!(super::re2post("\\p{L"), None);
+    }
+
+    #[test]
+    pub fn test_anchors() {
+        assert_eq!("^a.b.$.", super::re2post("^ab$").unwrap_or_default());
+        let graph = super::NFAGraph::new("^ab");
+        assert_eq!(graph.find("abab"), Some(Match { start: 0, end: 2 }));
+        assert_eq!(graph.find_iter("abab").count(), 1);
+        assert_eq!(graph.find("cab"), None);
+        let graph = super::NFAGraph::new("ab$");
+        assert_eq!(graph.find("abab"), Some(Match { start: 2, end: 4 }));
+        assert_eq!(graph.find("abc"), None);
+        assert!(super::NFAGraph::new("^a+$").is_match("aa"));
+        assert!(!super::NFAGraph::new("a$b").is_match("ab"));
+    }
+
+    #[test]
+    pub fn test_match_indices() {
+        let graph = super::NFAGraph::new("\\d");
+        assert_eq!(graph.match_indices("a1b2"), vec![(1, 2, "1"), (3, 4, "2")]);
+        let graph = super::NFAGraph::new("x\\d+");
+        assert_eq!(
+            graph.match_indices("x12 é x3"),
+            vec![(0, 3, "x12"), (7, 9, "x3")]
+        );
+        assert!(graph.match_indices("xy").is_empty());
+    }
+
+    #[test]
+    pub fn test_matched_strings() {
+        let graph = super::NFAGraph::new("a+");
+        assert_eq!(graph.matched_strings("aa b aaa"), vec!["aa", "aaa"]);
+        assert!(graph.matched_strings("bbb").is_empty());
+    }
+
+    #[test]
+    pub fn test_invalid_graph() {
+        let graph = super::NFAGraph::compile("a+b+.");
+        assert!(graph.is_valid());
+        assert_eq!(graph.try_is_match("ab"), Ok(true));
+
+        for post in ["a+b+", "a.", "a|", "*", "a-"] {
+            let graph = super::NFAGraph::compile(post);
+            assert!(!graph.is_valid());
+            assert!(!graph.is_match("ab"));
+            assert_eq!(graph.try_is_match("ab"), Err(MatchError::InvalidGraph));
+        }
+    }
+
+    #[test]
+    pub fn test_from_postfix() {
+        let graph = super::NFAGraph::from_postfix("ab|c.").unwrap();
+        assert!(graph.is_match("ac"));
+        assert!(graph.is_match("bc"));
+        let expected = super::NFAGraph::compile(&super::re2post("(a|b)c").unwrap());
+        assert_eq!(graph, expected);
+        assert!(super::NFAGraph::from_postfix("ab.").is_ok());
+        // "ab" is missing the `.` joining its operands
+        for post in ["ab", "a.", "|", "a*~~", "a-", "[ab", "\\"] {
+            assert_eq!(
+                super::NFAGraph::from_postfix(post).unwrap_err(),
+                RegexError::MalformedPostfix,
+                "{post}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_many_alternatives() {
+        let post = super::re2post("(a|b|c|d)").unwrap();
+        assert_eq!(post, "abcd|||");
+        for pattern in ["(a|b|c|d)", "a|b|c|d", "((a|b)|(c|d))", "(a|(b|(c|d)))"] {
+            let graph = super::NFAGraph::new(pattern);
+            for input in ["a", "b", "c", "d"] {
+                assert!(graph.is_match(input), "{pattern} {input}");
+            }
+            for input in ["", "e", "ab", "da", "abcd"] {
+                assert!(!graph.is_match(input), "{pattern} {input}");
+            }
+        }
+        // every branch keeps both of its edges
+        let graph = super::NFAGraph::new("x(ab|cd|ef|gh)y");
+        for input in ["xaby", "xcdy", "xefy", "xghy"] {
+            assert!(graph.is_match(input), "{input}");
+        }
+        for input in ["xay", "xady", "xy", "xabcdy"] {
+            assert!(!graph.is_match(input), "{input}");
+        }
+        let graph = super::NFAGraph::new("(a|b|c|d)+");
+        assert!(graph.is_match("dcbaabcd"));
+        assert!(!graph.is_match("abce"));
+        let graph = super::NFAGraph::new("(a||b|)c");
+        for input in ["ac", "bc", "c"] {
+            assert!(graph.is_match(input), "{input}");
+        }
+        let graph = super::NFAGraph::new("a|b|c|d");
+        for (i, input) in ["a", "b", "c", "d"].iter().enumerate() {
+            assert_eq!(graph.find_tagged(input).map(|(index, _)| index), Some(i));
+        }
+    }
+
+    #[test]
+    pub fn test_try_new_never_panics() {
+        let mut rng = crate::XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let pieces = [
+            "a",
+            "b",
+            "é",
+            "(",
+            ")",
+            "|",
+            "*",
+            "+",
+            "?",
+            ".",
+            "^",
+            "$",
+            "[",
+            "]",
+            "[^",
+            "-",
+            "\\",
+            "\\b",
+            "\\d",
+            "\\w",
+            "\\x4",
+            "\\u{",
+            "}",
+            "{",
+            "\\p{L}",
+            "\\P{",
+            "(?:",
+            "(?i:",
+            "(?P<n>",
+            "(?<",
+            ">",
+            "[:alpha:]",
+            "[[:",
+            ":]",
+            "~",
+            "_",
+            "1",
+            "\\e",
+            "9",
+        ];
+        for _ in 0..20_000 {
+            let pattern: String = rng.draw(&pieces, 10).concat();
+            let built = std::panic::catch_unwind(|| super::NFAGraph::try_new(&pattern));
+            assert!(built.is_ok(), "try_new panicked on {pattern:?}");
+            // the same text read as postfix has to be rejected just as quietly
+            let built = std::panic::catch_unwind(|| super::NFAGraph::from_postfix(&pattern));
+            assert!(built.is_ok(), "from_postfix panicked on {pattern:?}");
+        }
+        assert_eq!(
+            super::NFAGraph::from_postfix("a(99999999999999999999999)").unwrap_err(),
+            RegexError::MalformedPostfix
+        );
+    }
+
+    #[test]
+    pub fn test_dump() {
+        let dump = super::NFAGraph::new("ab").dump();
+        assert_eq!(
+            dump,
+            "state id: 0, state outs: {1: a}\n\
+             state id: 1, state outs: {2: ε}\n\
+             state id: 2, state outs: {3: b}\n\
+             state id: 3, state outs: {}\n"
+        );
+        let dump = super::NFAGraph::new("a(b|c)*").dump();
+        for _ in 0..10 {
+            assert_eq!(super::NFAGraph::new("a(b|c)*").dump(), dump);
+        }
+    }
+
+    #[test]
+    pub fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::NFAGraph>();
+        assert_send_sync::<crate::dfa::DFA>();
+        assert_send_sync::<crate::regex::Regex>();
+        assert_send_sync::<crate::set::RegexSet>();
+
+        let graph = std::sync::Arc::new(super::NFAGraph::new("a+b"));
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let graph = graph.clone();
+                std::thread::spawn(move || graph.is_match(&format!("{}b", "a".repeat(i + 1))))
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    pub fn test_par_matches() {
+        let graph = super::NFAGraph::new("(a|b)*abb");
+        let inputs: Vec<String> = (0..1000)
+            .map(|i: u32| format!("{:b}", i).replace('0', "a").replace('1', "b"))
+            .collect();
+        let expected: Vec<bool> = inputs.iter().map(|s| graph.is_match(s)).collect();
+        assert_eq!(graph.par_matches(&inputs), expected);
+        assert!(expected.iter().any(|m| *m));
+    }
+
+    #[test]
+    pub fn test_is_match_chars() {
+        let graph = super::NFAGraph::new("a+b+");
+        let stream = std::iter::repeat_n('a', 3).chain(std::iter::once('b'));
+        assert!(graph.is_match_chars(stream));
+        for input in ["aabb", "ab", "ba", "", "aab\u{e9}", "bbb"] {
+            assert_eq!(
+                graph.is_match_chars(input.chars()),
+                graph.is_match(input),
+                "{input}"
+            );
+        }
+        let graph = super::NFAGraph::new("\\ba\\b");
+        assert!(graph.is_match_chars(vec!['a']));
+        assert!(!graph.is_match_chars(std::iter::empty()));
+    }
+
+    #[test]
+    pub fn test_longest_prefix() {
+        let graph = super::NFAGraph::new("a+");
+        assert_eq!(graph.longest_prefix("aaab"), Some(3));
+        assert_eq!(graph.longest_prefix("baa"), None);
+        assert_eq!(super::NFAGraph::new("a*").longest_prefix("b"), Some(0));
+        // a longer partial match doesn't hide the last accepting position
+        let graph = super::NFAGraph::new("ab|abcd");
+        assert_eq!(graph.longest_prefix("abcx"), Some(2));
+        assert_eq!(super::NFAGraph::new("a+?").longest_prefix("aa"), Some(2));
+        assert_eq!(super::NFAGraph::new("é+").longest_prefix("ééa"), Some(4));
+    }
+
+    #[test]
+    pub fn test_shortest_prefix() {
+        let graph = super::NFAGraph::new("ab*");
+        assert_eq!(graph.shortest_prefix("abbbc"), Some(1));
+        assert_eq!(graph.longest_prefix("abbbc"), Some(4));
+        assert_eq!(graph.shortest_prefix("babc"), None);
+        assert_eq!(super::NFAGraph::new("a*").shortest_prefix("aa"), Some(0));
+        let graph = super::NFAGraph::new("abcd|ab");
+        assert_eq!(graph.shortest_prefix("abcd"), Some(2));
+        assert_eq!(super::NFAGraph::new("é+").shortest_prefix("ééa"), Some(2));
+        assert_eq!(super::NFAGraph::new("ab$").shortest_prefix("abc"), None);
+    }
+
+    #[test]
+    pub fn test_is_match_at() {
+        let graph = super::NFAGraph::new("b+");
+        assert!(graph.is_match_at("aabb", 2));
+        assert!(graph.is_match_at("aabb", 3));
+        assert!(!graph.is_match_at("aabb", 0));
+        assert!(!graph.is_match_at("aabb", 4));
+        assert!(!graph.is_match_at("aabb", 5));
+        // inside the two bytes of the é
+        assert!(!super::NFAGraph::new("_").is_match_at("éb", 1));
+        assert!(super::NFAGraph::new("\\bb").is_match_at("a b", 2));
+        assert!(!super::NFAGraph::new("\\bb").is_match_at("ab", 1));
+    }
+
+    #[test]
+    pub fn test_sample_matches() {
+        let graph = super::NFAGraph::new("a(b|c)");
+        assert_eq!(graph.sample_matches(10, 100), vec!["ab", "ac"]);
+        let graph = super::NFAGraph::new("a+");
+        assert_eq!(graph.sample_matches(3, 100), vec!["a", "aa", "aaa"]);
+        assert_eq!(graph.sample_matches(3, 2), vec!["a", "aa"]);
+        let graph = super::NFAGraph::new("x*");
+        assert_eq!(graph.sample_matches(1, 100), vec!["", "x"]);
+        // wildcards and negated classes pick an example
+        let graph = super::NFAGraph::new("[^a-z].\\p{Lu}");
+        let samples = graph.sample_matches(3, 100);
+        assert_eq!(samples, vec!["AaA"]);
+        // assertions are checked against the neighbouring chars
+        let graph = super::NFAGraph::new("(a|-)\\b(b|-)");
+        assert_eq!(graph.sample_matches(2, 100), vec!["-b", "a-"]);
+        for sample in super::NFAGraph::new("(ab|c)*d?").sample_matches(4, 50) {
+            assert!(
+                super::NFAGraph::new("(ab|c)*d?").is_match(&sample),
+                "{sample}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_char_index() {
+        let graph = super::NFAGraph::new("[a-zA-Z0-9_]+x|[0-5]y|z.");
+        let mut unindexed = graph.clone();
+        unindexed.char_index.clear();
+        let all: Vec<StateId> = graph.states.keys().copied().collect();
+        for c in "azAZ09_5xyz-é\n".chars() {
+            let mut indexed = graph.move2(c, &all);
+            let mut scanned = unindexed.move2(c, &all);
+            indexed.sort();
+            scanned.sort();
+            assert_eq!(indexed, scanned, "{c}");
+        }
+        assert!(graph.is_match("ab_9x"));
+        assert!(graph.is_match("3y"));
+        assert!(!graph.is_match("6y"));
+    }
+
+    #[test]
+    pub fn test_is_match_within() {
+        let graph = super::NFAGraph::new("a+b+");
+        let input = format!("{}b", "a".repeat(1000));
+        assert_eq!(
+            graph.is_match_within(&input, 10),
+            Err(MatchError::StepLimitExceeded)
+        );
+        assert_eq!(graph.is_match_within(&input, 10_000), Ok(true));
+        assert_eq!(graph.is_match_within(&input[..1000], 10_000), Ok(false));
+        // a dead state set ends the search before the limit is hit
+        assert_eq!(graph.is_match_within(&format!("b{}", input), 10), Ok(false));
     }
 }
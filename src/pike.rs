@@ -0,0 +1,267 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::nfa::{Context, Match, NFAGraph, StateId, Transition};
+
+type Slots = Vec<Option<usize>>;
+
+// A Pike VM: the graph is simulated over the input one char at a time like
+// `is_match` does, but every thread carries its own capture slots. Where two
+// threads meet in the same state only one can go on. Like in `find`, the
+// one that took fewer lazy edges wins, so lazy quantifiers repeat as little
+// as they can. Between equally lazy threads the groups decide: the first
+// group where they differ, in order, ranks a thread that took part in it
+// above one that didn't, then the earlier start, then the later end, and a
+// group still open ends later than a closed one. So the groups come out
+// leftmost-longest like the whole match, in time linear in the input.
+#[derive(Debug, Clone, Copy)]
+pub struct PikeVm<'g> {
+    graph: &'g NFAGraph,
+}
+
+impl<'g> PikeVm<'g> {
+    pub fn new(graph: &'g NFAGraph) -> Self {
+        Self { graph }
+    }
+
+    // Full match, like `NFAGraph::is_match`.
+    pub fn is_match(&self, s: &str) -> bool {
+        #[cfg(feature = "unicode-normalization")]
+        if self.graph.normalize {
+            use unicode_normalization::UnicodeNormalization;
+            let normalized: String = s.nfc().collect();
+            return self.run(&normalized);
+        }
+        self.run(s)
+    }
+
+    fn run(&self, s: &str) -> bool {
+        self.graph.valid
+            && self
+                .slots(
+                    s,
+                    Match {
+                        start: 0,
+                        end: s.len(),
+                    },
+                )
+                .is_some()
+    }
+
+    // The slots of the best ranked thread that consumes exactly the span `m`
+    // of `s`, two per group with group 0 first.
+    pub(crate) fn slots(&self, s: &str, m: Match) -> Option<Slots> {
+        let graph = self.graph;
+        let mut slots = vec![None; 2 * graph.group_names.len() + 2];
+        slots[0] = Some(m.start);
+        slots[1] = Some(m.end);
+        let mut threads = Threads::default();
+        let thread = Thread {
+            lazy: 0,
+            counters: vec![0; graph.counters],
+            slots,
+        };
+        self.add_threads(&mut threads, s, m.start, vec![(graph.start, thread)]);
+        let mut pos = m.start;
+        while pos < m.end {
+            let c = s[pos..].chars().next()?;
+            let mut next = Vec::new();
+            for (id, thread) in threads.list {
+                for (target, transition) in self.edges(id) {
+                    if graph.takes(transition, c) {
+                        next.push((target, thread.clone()));
+                    }
+                }
+            }
+            if next.is_empty() {
+                return None;
+            }
+            pos += c.len_utf8();
+            threads = Threads::default();
+            self.add_threads(&mut threads, s, pos, next);
+        }
+        threads
+            .list
+            .into_iter()
+            .filter(|(id, _)| graph.is_accepting(id))
+            .max_by(|(_, a), (_, b)| a.rank(b))
+            .map(|(_, thread)| thread.slots)
+    }
+
+    // Adds the threads in `seeds` and everything they reach at `pos` without
+    // consuming input. Better ranked threads go first, so in a state reached
+    // from several of them the best one stays: those that took fewer lazy
+    // edges, with a lazy edge putting a thread back among the next ones, and
+    // among equally lazy ones those with the better groups.
+    fn add_threads(
+        &self,
+        threads: &mut Threads,
+        s: &str,
+        pos: usize,
+        seeds: Vec<(StateId, Thread)>,
+    ) {
+        let ctx = Context::at(s, pos);
+        let mut levels: BTreeMap<usize, Vec<(StateId, Thread)>> = BTreeMap::new();
+        for (id, thread) in seeds {
+            levels.entry(thread.lazy).or_default().push((id, thread));
+        }
+        while let Some((lazy, mut level)) = levels.pop_first() {
+            level.sort_by(|(_, a), (_, b)| b.rank(a));
+            for seed in level {
+                let mut stack = vec![seed];
+                while let Some((id, thread)) = stack.pop() {
+                    if !threads.seen.insert((id, thread.counters.clone())) {
+                        continue;
+                    }
+                    // the stack pops the last job first
+                    for (target, transition) in self.edges(id).into_iter().rev() {
+                        match transition {
+                            Transition::Save(slot) => {
+                                let mut thread = thread.clone();
+                                thread.slots[*slot] = Some(pos);
+                                stack.push((target, thread));
+                            }
+                            Transition::LazyEpsilon => {
+                                let thread = Thread {
+                                    lazy: lazy + 1,
+                                    ..thread.clone()
+                                };
+                                levels.entry(lazy + 1).or_default().push((target, thread));
+                            }
+                            Transition::Counter(op) => {
+                                if let Some(counters) = op.apply(&thread.counters) {
+                                    let thread = Thread {
+                                        counters,
+                                        ..thread.clone()
+                                    };
+                                    stack.push((target, thread));
+                                }
+                            }
+                            Transition::Assert(assertion) if assertion.holds(ctx) => {
+                                stack.push((target, thread.clone()))
+                            }
+                            t if t.is_epsilon() => stack.push((target, thread.clone())),
+                            _ => {}
+                        }
+                    }
+                    threads.list.push((id, thread));
+                }
+            }
+        }
+    }
+
+    // The edges leaving `id` ordered by target, so threads that rank the
+    // same are settled the same way on every run.
+    fn edges(&self, id: StateId) -> Vec<(StateId, &'g Transition)> {
+        let mut edges: Vec<(StateId, &Transition)> = self.graph.states[&id]
+            .outs
+            .iter()
+            .map(|(target, transition)| (*target, transition))
+            .collect();
+        edges.sort_by_key(|(target, _)| *target);
+        edges
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Thread {
+    // lazy edges taken so far
+    lazy: usize,
+    // one per counted loop, see `CounterOp`
+    counters: Vec<usize>,
+    slots: Slots,
+}
+
+impl Thread {
+    // How this thread compares to `other` by the rule described on
+    // `PikeVm`, `Greater` if it is preferred. Group 0 is the same for all
+    // threads and skipped.
+    fn rank(&self, other: &Thread) -> Ordering {
+        let key = |pair: &[Option<usize>]| match (pair[0], pair[1]) {
+            (Some(start), Some(end)) if end >= start => Some((Reverse(start), end)),
+            // not closed since it last started
+            (Some(start), _) => Some((Reverse(start), usize::MAX)),
+            _ => None,
+        };
+        let groups = self
+            .slots
+            .chunks(2)
+            .zip(other.slots.chunks(2))
+            .skip(1)
+            .map(|(a, b)| key(a).cmp(&key(b)))
+            .find(|order| order.is_ne())
+            .unwrap_or(Ordering::Equal);
+        other.lazy.cmp(&self.lazy).then(groups)
+    }
+}
+
+// The threads at one offset, and the states they're in together with their
+// counters, since threads with different counters don't meet.
+#[derive(Default)]
+struct Threads {
+    list: Vec<(StateId, Thread)>,
+    seen: HashSet<(StateId, Vec<usize>)>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PikeVm;
+    use crate::nfa::{Match, NFAGraph};
+
+    #[test]
+    fn test_is_match() {
+        let inputs = [
+            "", "abb", "aabb", "ab", "abcbd", "ad", "a\nb", "abba", "12-345", "a b",
+        ];
+        for pattern in [
+            "(a|b)*abb",
+            "a(b|c)*d",
+            "a.b",
+            "[^x]+",
+            "(\\d+)-(\\d+)",
+            "\\ba\\b.*",
+            "(a*?)(b*)",
+        ] {
+            let graph = NFAGraph::new(pattern);
+            let vm = PikeVm::new(&graph);
+            for input in inputs {
+                assert_eq!(
+                    vm.is_match(input),
+                    graph.is_match(input),
+                    "{pattern} {input:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_slots() {
+        let graph = NFAGraph::new("(\\d+)-(\\d+)");
+        let vm = PikeVm::new(&graph);
+        let slots = vm.slots("x 12-345", Match { start: 2, end: 8 }).unwrap();
+        assert_eq!(
+            slots,
+            vec![Some(2), Some(8), Some(2), Some(4), Some(5), Some(8)]
+        );
+        let caps = graph.captures("x 12-345").unwrap();
+        assert_eq!((caps.str(1), caps.str(2)), (Some("12"), Some("345")));
+
+        // the groups in order start as early and end as late as they can
+        let graph = NFAGraph::new("(a*)(a*)");
+        let slots = PikeVm::new(&graph).slots("aa", Match { start: 0, end: 2 });
+        assert_eq!(slots.unwrap()[2..], [Some(0), Some(2), Some(2), Some(2)]);
+        let graph = NFAGraph::new("(a|ab)(b*)");
+        let slots = PikeVm::new(&graph).slots("ab", Match { start: 0, end: 2 });
+        assert_eq!(slots.unwrap()[2..], [Some(0), Some(2), Some(2), Some(2)]);
+        // but a lazy group takes as little as it can
+        let graph = NFAGraph::new("(a*?)(a*)");
+        let slots = PikeVm::new(&graph).slots("aa", Match { start: 0, end: 2 });
+        assert_eq!(slots.unwrap()[2..], [Some(0), Some(0), Some(0), Some(2)]);
+        let graph = NFAGraph::new("(a+?)(a*)(b??)b");
+        let slots = PikeVm::new(&graph).slots("aaab", Match { start: 0, end: 4 });
+        assert_eq!(
+            slots.unwrap()[2..],
+            [Some(0), Some(1), Some(1), Some(3), Some(3), Some(3)]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+#[cfg(feature = "cache")]
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::builder::RegexBuilder;
+use crate::captures::Captures;
+use crate::dfa::DFA;
+use crate::error::RegexError;
+use crate::nfa::{self, Match, NFAGraph};
+
+const PRECOMPILED_MAGIC: &[u8; 4] = b"RXRS";
+
+// Number of graphs `Regex::cached` keeps around.
+#[cfg(feature = "cache")]
+pub const CACHE_CAPACITY: usize = 64;
+
+// patterns with their graphs, the most recently used last
+#[cfg(feature = "cache")]
+type Cache = Mutex<Vec<(String, Arc<NFAGraph>)>>;
+
+// The stable face of the crate: the automaton behind it is free to change.
+// `NFAGraph` stays public for anything this doesn't cover.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    imp: Imp,
+}
+
+#[derive(Debug, Clone)]
+enum Imp {
+    Nfa(NFAGraph),
+    Dfa(DFA),
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let graph = RegexBuilder::new(pattern).build()?;
+        Ok(Regex {
+            imp: Imp::Nfa(graph),
+        })
+    }
+
+    pub fn is_match(&self, s: &str) -> bool {
+        match &self.imp {
+            Imp::Nfa(graph) => graph.is_match(s),
+            Imp::Dfa(dfa) => dfa.is_match(s),
+        }
+    }
+
+    pub fn find(&self, s: &str) -> Option<Match> {
+        self.find_at(s, 0)
+    }
+
+    fn find_at(&self, s: &str, start: usize) -> Option<Match> {
+        match &self.imp {
+            Imp::Nfa(graph) => graph.find_at(s, start),
+            Imp::Dfa(dfa) => dfa.find_at(s, start),
+        }
+    }
+
+    pub fn find_iter<'r, 't>(&'r self, s: &'t str) -> Matches<'r, 't> {
+        Matches {
+            regex: self,
+            text: s,
+            pos: 0,
+        }
+    }
+
+    // A precompiled regex has lost its groups, so only group 0 is reported.
+    pub fn captures<'t>(&self, s: &'t str) -> Option<Captures<'_, 't>> {
+        match &self.imp {
+            Imp::Nfa(graph) => graph.captures(s),
+            Imp::Dfa(dfa) => dfa.find_at(s, 0).map(|m| Captures::whole(s, m)),
+        }
+    }
+
+    // Replaces every match with `template`, see `Captures::expand`.
+    pub fn replace_all(&self, s: &str, template: &str) -> String {
+        match &self.imp {
+            Imp::Nfa(graph) => graph.replace_all(s, template),
+            Imp::Dfa(_) => {
+                let mut out = String::with_capacity(s.len());
+                let mut last = 0;
+                for m in self.find_iter(s) {
+                    out.push_str(&s[last..m.start]);
+                    Captures::whole(s, m).expand(template, &mut out);
+                    last = m.end;
+                }
+                out.push_str(&s[last..]);
+                out
+            }
+        }
+    }
+
+    // The pieces of `s` between the matches, like `str::split`.
+    pub fn split<'t>(&self, s: &'t str) -> Vec<&'t str> {
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for m in self.find_iter(s) {
+            pieces.push(&s[last..m.start]);
+            last = m.end;
+        }
+        pieces.push(&s[last..]);
+        pieces
+    }
+
+    // Compiles `pattern` with the builder's defaults, or hands out the graph
+    // compiled by an earlier call for the same pattern. The process-wide
+    // cache keeps the `CACHE_CAPACITY` most recently used patterns, errors
+    // aren't cached.
+    #[cfg(feature = "cache")]
+    pub fn cached(pattern: &str) -> Result<Arc<NFAGraph>, RegexError> {
+        static CACHE: OnceLock<Cache> = OnceLock::new();
+        let cache = CACHE.get_or_init(|| Mutex::new(Vec::new()));
+        // a panic while holding the lock can't leave the list inconsistent
+        let mut entries = cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = entries.iter().position(|(p, _)| p == pattern) {
+            let entry = entries.remove(i);
+            let graph = entry.1.clone();
+            entries.push(entry);
+            return Ok(graph);
+        }
+        let graph = Arc::new(RegexBuilder::new(pattern).build()?);
+        if entries.len() >= CACHE_CAPACITY {
+            entries.remove(0);
+        }
+        entries.push((pattern.to_string(), graph.clone()));
+        Ok(graph)
+    }
+
+    // Compiles `pattern` ahead of time (e.g. from a build script) into a
+    // minimized DFA and serializes it together with the crate version.
+    // Load the result with `from_precompiled`.
+    pub fn precompile(pattern: &str) -> Result<Vec<u8>, RegexError> {
+        let graph = RegexBuilder::new(pattern).build()?;
+        let dfa = graph.to_dfa()?.minimize();
+        let version = env!("CARGO_PKG_VERSION").as_bytes();
+        let mut out = PRECOMPILED_MAGIC.to_vec();
+        out.push(version.len() as u8);
+        out.extend_from_slice(version);
+        out.extend_from_slice(&dfa.to_bytes());
+        Ok(out)
+    }
+
+    // Loads an artifact from `precompile`. Artifacts written by a different
+    // version of the crate are rejected, since the layout may have changed.
+    pub fn from_precompiled(data: &[u8]) -> Result<Regex, RegexError> {
+        let rest = data
+            .strip_prefix(PRECOMPILED_MAGIC)
+            .ok_or(RegexError::InvalidPrecompiled)?;
+        let (len, rest) = rest.split_first().ok_or(RegexError::InvalidPrecompiled)?;
+        let len = *len as usize;
+        if rest.len() < len {
+            return Err(RegexError::InvalidPrecompiled);
+        }
+        if &rest[..len] != env!("CARGO_PKG_VERSION").as_bytes() {
+            return Err(RegexError::VersionMismatch);
+        }
+        let dfa = DFA::from_bytes(&rest[len..]).map_err(|_| RegexError::InvalidPrecompiled)?;
+        Ok(Regex { imp: Imp::Dfa(dfa) })
+    }
+}
+
+// Iterator over the non-overlapping matches of a `Regex`.
+#[derive(Debug, Clone)]
+pub struct Matches<'r, 't> {
+    regex: &'r Regex,
+    text: &'t str,
+    pos: usize,
+}
+
+impl Iterator for Matches<'_, '_> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        let allow_empty = match &self.regex.imp {
+            Imp::Nfa(graph) => graph.allow_empty,
+            Imp::Dfa(_) => true,
+        };
+        let (regex, text) = (self.regex, self.text);
+        nfa::next_match(text, &mut self.pos, allow_empty, |at| {
+            regex.find_at(text, at)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Regex;
+    use crate::error::RegexError;
+    use crate::nfa::Match;
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_cached() {
+        use std::sync::Arc;
+
+        let a = Regex::cached("c(a|b)+").unwrap();
+        let b = Regex::cached("c(a|b)+").unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(a.is_match("cab"));
+        assert_eq!(
+            Regex::cached("(c").unwrap_err(),
+            RegexError::UnbalancedParen(0)
+        );
+        // the least recently used pattern is dropped first
+        for i in 0..super::CACHE_CAPACITY {
+            Regex::cached(&format!("x{}", i)).unwrap();
+        }
+        assert!(!Arc::ptr_eq(&a, &Regex::cached("c(a|b)+").unwrap()));
+    }
+
+    #[test]
+    fn test_regex() {
+        let re = Regex::new("(?P<word>[a-z]+)=(\\d+)").unwrap();
+        assert!(re.is_match("ab=12"));
+        assert!(!re.is_match("ab=12;"));
+        let text = "x=1; yy=22; z";
+        assert_eq!(re.find(text), Some(Match { start: 0, end: 3 }));
+        let spans: Vec<Match> = re.find_iter(text).collect();
+        assert_eq!(spans.len(), 2);
+        let caps = re.captures(text).unwrap();
+        assert_eq!(&caps[2], "1");
+        assert_eq!(caps.str(1), Some("x"));
+        assert_eq!(re.replace_all(text, "$2:$word"), "1:x; 22:yy; z");
+        assert_eq!(re.split(text), vec!["", "; ", "; z"]);
+        assert_eq!(
+            Regex::new("(a").unwrap_err(),
+            RegexError::UnbalancedParen(0)
+        );
+
+        // a precompiled regex searches the same, without groups
+        let re = Regex::from_precompiled(&Regex::precompile("b+").unwrap()).unwrap();
+        assert_eq!(re.find("abbc"), Some(Match { start: 1, end: 3 }));
+        assert_eq!(re.split("abbcb"), vec!["a", "c", ""]);
+        assert_eq!(re.replace_all("abbc", "<$0>"), "a<bb>c");
+        assert_eq!(re.captures("abbc").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_precompiled() {
+        let inputs = ["", "a", "ab", "abcb", "abx", "ba", "abcbcbcbx"];
+        for pattern in ["a(b|c)*", "a+b?", "(a|b)*abb", "a.*?b"] {
+            let fresh = Regex::new(pattern).unwrap();
+            let artifact = Regex::precompile(pattern).unwrap();
+            let loaded = Regex::from_precompiled(&artifact).unwrap();
+            for input in inputs {
+                assert_eq!(loaded.is_match(input), fresh.is_match(input));
+            }
+        }
+    }
+
+    #[test]
+    fn test_precompiled_rejected() {
+        let mut artifact = Regex::precompile("a+").unwrap();
+        assert!(Regex::from_precompiled(&artifact).is_ok());
+        assert_eq!(
+            Regex::from_precompiled(&artifact[..artifact.len() - 2]).unwrap_err(),
+            RegexError::InvalidPrecompiled
+        );
+        assert_eq!(
+            Regex::from_precompiled(b"nope").unwrap_err(),
+            RegexError::InvalidPrecompiled
+        );
+        // the version string starts right after the magic and its length
+        artifact[5] = b'9';
+        assert_eq!(
+            Regex::from_precompiled(&artifact).unwrap_err(),
+            RegexError::VersionMismatch
+        );
+        assert_eq!(
+            Regex::precompile("a\\b").unwrap_err(),
+            RegexError::Unsupported("assertions in a DFA")
+        );
+    }
+}
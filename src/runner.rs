@@ -0,0 +1,105 @@
+use crate::nfa::{Context, NFAGraph, StateId, Threads};
+
+// Runs a graph over input that arrives in pieces, so feeding "aa" and then
+// "bb" is the same as feeding "aabb" at once. The closure of the states
+// reached by the last char is only taken once the following char is known,
+// because assertions depend on it.
+#[derive(Debug, Clone)]
+pub struct NfaRunner<'g> {
+    graph: &'g NFAGraph,
+    pending: Threads,
+    prev: Option<char>,
+}
+
+impl<'g> NfaRunner<'g> {
+    pub fn new(graph: &'g NFAGraph) -> Self {
+        let pending = if graph.valid {
+            graph.threads(graph.start)
+        } else {
+            Threads::States(Vec::new())
+        };
+        Self {
+            graph,
+            pending,
+            prev: None,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &str) {
+        for c in chunk.chars() {
+            if self.pending.is_empty() {
+                return;
+            }
+            let active = self.closure(Some(c));
+            self.pending = self.graph.advance(&active, |ids| self.graph.move2(c, ids));
+            self.prev = Some(c);
+        }
+    }
+
+    // Whether everything fed so far is matched by the pattern.
+    pub fn is_match_so_far(&self) -> bool {
+        self.graph.accepts(&self.closure(None))
+    }
+
+    // The active states if the input ended here.
+    pub fn active_states(&self) -> Vec<StateId> {
+        self.closure(None).states()
+    }
+
+    fn closure(&self, next: Option<char>) -> Threads {
+        let ctx = Context {
+            prev: self.prev,
+            next,
+        };
+        self.graph.close(self.pending.clone(), ctx)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.graph);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NfaRunner;
+    use crate::nfa::NFAGraph;
+
+    #[test]
+    fn test_feed() {
+        let graph = NFAGraph::new("a+b+");
+        let mut runner = NfaRunner::new(&graph);
+        runner.feed("aa");
+        assert!(!runner.is_match_so_far());
+        runner.feed("bb");
+        assert!(runner.is_match_so_far());
+
+        let mut at_once = NfaRunner::new(&graph);
+        at_once.feed("aabb");
+        assert!(at_once.is_match_so_far());
+        let mut chunked = runner.active_states();
+        let mut whole = at_once.active_states();
+        chunked.sort();
+        whole.sort();
+        assert_eq!(chunked, whole);
+
+        runner.feed("a");
+        assert!(!runner.is_match_so_far());
+        runner.feed("b");
+        assert!(!runner.is_match_so_far());
+        runner.reset();
+        runner.feed("ab");
+        assert!(runner.is_match_so_far());
+    }
+
+    #[test]
+    fn test_feed_boundary() {
+        let graph = NFAGraph::new("a+\\b");
+        let mut runner = NfaRunner::new(&graph);
+        runner.feed("a");
+        assert!(runner.is_match_so_far());
+        runner.feed("a");
+        assert!(runner.is_match_so_far());
+        runner.feed("b");
+        assert!(!runner.is_match_so_far());
+    }
+}
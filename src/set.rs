@@ -0,0 +1,97 @@
+use crate::builder::DEFAULT_SIZE_LIMIT;
+use crate::error::RegexError;
+use crate::nfa::{parse_pattern, CompileConfig, Context, NFAGraph, State, StateId, Transition};
+
+// Several patterns compiled into one graph, so a single pass over the input
+// tells which of them match. The start state has an epsilon edge into every
+// pattern's fragment and each accepting state remembers its pattern.
+#[derive(Debug, Clone)]
+pub struct RegexSet {
+    graph: NFAGraph,
+    ends: Vec<(StateId, usize)>,
+    len: usize,
+}
+
+impl RegexSet {
+    pub fn new<I, S>(patterns: I) -> Result<RegexSet, RegexError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut graph = NFAGraph::empty();
+        let mut start = State::new(StateId(graph.last_id));
+        graph.last_id += 1;
+        let mut ends = Vec::new();
+        let mut len = 0;
+        for pattern in patterns {
+            let (post, _) = parse_pattern(pattern.as_ref(), false, true)?;
+            // every pattern gets the same budget as a standalone build
+            let config = CompileConfig {
+                size_limit: graph.last_id + DEFAULT_SIZE_LIMIT,
+                ..CompileConfig::default()
+            };
+            let (frag, _) = NFAGraph::try_compile_subgraph(&post, &mut graph, &config)?;
+            start.outs.insert(frag.start, Transition::Epsilon);
+            ends.extend(frag.end.into_iter().map(|end| (end, len)));
+            len += 1;
+        }
+        graph.start = start.id;
+        graph.ends = ends.iter().map(|(end, _)| *end).collect();
+        graph.states.insert(start.id, start);
+        graph.index_chars();
+        Ok(RegexSet { graph, ends, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Indices, in ascending order, of the patterns that match all of `s`.
+    pub fn matches(&self, s: &str) -> Vec<usize> {
+        let graph = &self.graph;
+        let mut current = graph.closure(vec![graph.start], Context::at(s, 0));
+        for (i, c) in s.char_indices() {
+            let ctx = Context::at(s, i + c.len_utf8());
+            current = graph.closure(graph.move2(c, &current), ctx);
+            if current.is_empty() {
+                return Vec::new();
+            }
+        }
+        let mut matched: Vec<usize> = self
+            .ends
+            .iter()
+            .filter(|(end, _)| current.contains(end))
+            .map(|(_, index)| *index)
+            .collect();
+        matched.sort_unstable();
+        matched.dedup();
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegexSet;
+    use crate::error::RegexError;
+
+    #[test]
+    fn test_matches() {
+        let set = RegexSet::new(["a+b+", "a(b|c)*", "ba"]).unwrap();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.matches("abb"), vec![0, 1]);
+        assert_eq!(set.matches("ba"), vec![2]);
+        assert_eq!(set.matches("x"), Vec::<usize>::new());
+
+        let set = RegexSet::new(["a", "", "a*"]).unwrap();
+        assert_eq!(set.matches(""), vec![1, 2]);
+        assert!(RegexSet::new(Vec::<String>::new()).unwrap().is_empty());
+        assert_eq!(
+            RegexSet::new(["a", "(b"]).unwrap_err(),
+            RegexError::UnbalancedParen(0)
+        );
+    }
+}
@@ -0,0 +1,75 @@
+// Text that isn't one contiguous `&str`, like the rope or piece table of an
+// editor, for `NFAGraph::is_match_source` and `NFAGraph::find_source`.
+// Positions are byte offsets into the UTF-8 encoding of the whole text, the
+// same offsets `Match` reports for a `&str`.
+pub trait TextSource {
+    // The chars from byte offset `pos` to the end, across segment
+    // boundaries. `pos` is always a char boundary.
+    fn chars_from(&self, pos: usize) -> impl Iterator<Item = char>;
+
+    // The length of the text in bytes.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TextSource for str {
+    fn chars_from(&self, pos: usize) -> impl Iterator<Item = char> {
+        self[pos..].chars()
+    }
+
+    fn len(&self) -> usize {
+        str::len(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextSource;
+    use crate::nfa::{Match, NFAGraph};
+
+    // a text split in two, as a rope would hold it
+    struct Segments<'a>(&'a str, &'a str);
+
+    impl TextSource for Segments<'_> {
+        fn chars_from(&self, pos: usize) -> impl Iterator<Item = char> {
+            let split = self.0.len();
+            let (first, second) = if pos < split {
+                (&self.0[pos..], self.1)
+            } else {
+                ("", &self.1[pos - split..])
+            };
+            first.chars().chain(second.chars())
+        }
+
+        fn len(&self) -> usize {
+            self.0.len() + self.1.len()
+        }
+    }
+
+    #[test]
+    fn test_segments() {
+        let text = Segments("xx ab", "cd é!");
+        assert_eq!(text.len(), 11);
+        assert_eq!(text.chars_from(3).collect::<String>(), "abcd é!");
+
+        // the match spans the boundary between the two segments
+        let graph = NFAGraph::new("\\bab+cd\\b");
+        assert_eq!(graph.find_source(&text), Some(Match { start: 3, end: 7 }));
+        assert_eq!(graph.find_source(&text), graph.find("xx abcd é!"));
+        assert_eq!(
+            NFAGraph::new("é!").find_source(&text),
+            Some(Match { start: 8, end: 11 })
+        );
+        assert!(NFAGraph::new("xx abcd é!").is_match_source(&text));
+        assert!(!NFAGraph::new("xx ab").is_match_source(&text));
+        assert_eq!(NFAGraph::new("z").find_source(&text), None);
+
+        // and a plain `&str` is a source too
+        let graph = NFAGraph::new("b+c");
+        assert_eq!(graph.find_source("abbcd"), graph.find("abbcd"));
+        assert!(graph.is_match_source("bbc"));
+    }
+}